@@ -0,0 +1,534 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::config::{Config, GeminiOverrides};
+use crate::error::{self, ErrorKind};
+use crate::llm::{BatchEntry, BatchResult, LlmClient, LlmResponse, Usage};
+use crate::log::{self, Logger};
+use crate::output;
+use crate::pool;
+use crate::prompts::Prompt;
+use crate::redact::Redactor;
+use crate::template::Vars;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Item {
+    id: String,
+    input: String,
+    #[serde(default)]
+    prompt: Option<String>,
+    #[serde(default)]
+    prompt_text: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    vars: HashMap<String, String>,
+    #[serde(default)]
+    overrides: GeminiOverrides,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Outcome {
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip)]
+    kind: Option<ErrorKind>,
+}
+
+pub struct Request<'a> {
+    pub input: &'a Path,
+    pub output: &'a Path,
+    pub jobs: usize,
+    pub retry_failed: bool,
+    pub logger: &'a Logger,
+}
+
+pub fn run(client: &LlmClient, config: &Config, vars: &Vars, request: &Request) -> Result<()> {
+    let items = read_items(request.input)?;
+    let outcomes = read_outcomes(request.output)?;
+    let done = read_done(&outcomes, request.retry_failed);
+    let pending: Vec<Item> = items
+        .into_iter()
+        .filter(|item| !done.contains(&item.id))
+        .collect();
+
+    let failures = read_failures(&outcomes);
+    for item in &pending {
+        if let Some((count, error)) = failures.get(item.id.as_str()) {
+            request.logger.log(
+                log::INFO,
+                "retry",
+                &format!("{} attempt {}, last error: {error}", item.id, count + 1),
+            );
+        }
+    }
+
+    let total = pending.len();
+    let mut failed = Vec::new();
+    let mut file = open_output(request.output)?;
+    let redactor = Redactor::new(&config.redact)?;
+
+    pool::run(
+        pending,
+        request.jobs,
+        |item| process(client, config, vars, &redactor, item),
+        |_, outcome| {
+            if let Some(ref e) = outcome.error {
+                failed.push(outcome.kind.unwrap_or(ErrorKind::Other));
+                eprintln!("Error processing {}: {e}", outcome.id);
+            }
+            writeln!(file, "{}", serde_json::to_string(&outcome)?)?;
+            file.flush()?;
+            Ok(())
+        },
+    )?;
+
+    error::failures(&failed, total, "items")
+}
+
+pub fn submit(
+    client: &LlmClient,
+    config: &Config,
+    vars: &Vars,
+    input: &Path,
+    name: &str,
+) -> Result<()> {
+    let redactor = Redactor::new(&config.redact)?;
+    let entries = read_items(input)?
+        .into_iter()
+        .map(|item| {
+            let prompt = resolve(config, &item)?;
+            Ok(BatchEntry {
+                prompt: render(vars, &redactor, &item, prompt.as_ref())?,
+                overrides: overrides(prompt.as_ref(), &item),
+                key: item.id,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let job = client.submit_batch(name, &entries)?;
+    println!("{}", job.name);
+    Ok(())
+}
+
+pub fn status(client: &LlmClient, name: &str) -> Result<()> {
+    let job = client.batch_status(name)?;
+    println!(
+        "{}: {} ({}/{} succeeded, {} failed, {} pending)",
+        job.name,
+        job.state,
+        job.succeeded_count,
+        job.request_count,
+        job.failed_count,
+        job.pending_count
+    );
+    Ok(())
+}
+
+pub fn fetch(client: &LlmClient, name: &str, output: &Path) -> Result<()> {
+    let mut content = String::new();
+    for result in client.batch_results(name)? {
+        content.push_str(&serde_json::to_string(&Outcome::from(result))?);
+        content.push('\n');
+    }
+    output::write_atomic(output, &content)
+}
+
+pub fn cancel(client: &LlmClient, name: &str) -> Result<()> {
+    client.cancel_batch(name)
+}
+
+impl Outcome {
+    fn new(id: String, result: Result<LlmResponse>) -> Self {
+        match result {
+            Ok(response) => {
+                let complete = response.ensure_complete().err();
+                Outcome {
+                    id,
+                    error: complete.as_ref().map(|e| format!("{e:#}")),
+                    kind: complete.as_ref().map(error::kind),
+                    text: Some(response.text),
+                    usage: response.usage,
+                    finish_reason: response.finish_reason,
+                }
+            }
+            Err(e) => Outcome {
+                id,
+                text: None,
+                usage: None,
+                finish_reason: None,
+                error: Some(format!("{e:#}")),
+                kind: Some(error::kind(&e)),
+            },
+        }
+    }
+}
+
+impl From<BatchResult> for Outcome {
+    fn from(result: BatchResult) -> Self {
+        Outcome::new(result.key, result.response)
+    }
+}
+
+fn process(
+    client: &LlmClient,
+    config: &Config,
+    vars: &Vars,
+    redactor: &Redactor,
+    item: Item,
+) -> Outcome {
+    let result = send(client, config, vars, redactor, &item);
+    Outcome::new(item.id, result)
+}
+
+fn send(
+    client: &LlmClient,
+    config: &Config,
+    vars: &Vars,
+    redactor: &Redactor,
+    item: &Item,
+) -> Result<LlmResponse> {
+    let prompt = resolve(config, item)?;
+    let input_full = render(vars, redactor, item, prompt.as_ref())?;
+    client
+        .with_overrides(&overrides(prompt.as_ref(), item))?
+        .with_prompt_name(item.prompt.as_deref())
+        .send_request(&input_full)
+}
+
+fn resolve(config: &Config, item: &Item) -> Result<Option<Prompt>> {
+    match (&item.prompt, &item.prompt_text) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "Item '{}': use either 'prompt' or 'prompt_text', not both",
+            item.id
+        )),
+        (Some(name), None) => crate::resolve_prompt(config, Some(name)),
+        (None, text) => Ok(text.as_deref().map(Prompt::from_text)),
+    }
+}
+
+fn render(
+    vars: &Vars,
+    redactor: &Redactor,
+    item: &Item,
+    prompt: Option<&Prompt>,
+) -> Result<String> {
+    let mut vars = vars.with_files(Vec::new());
+    vars.user.extend(item.vars.clone());
+    let input = redactor.apply(&format!("item '{}'", item.id), &item.input)?;
+    crate::compose(prompt, item.message.as_deref(), &input, &vars)
+}
+
+fn overrides(prompt: Option<&Prompt>, item: &Item) -> GeminiOverrides {
+    match prompt {
+        Some(prompt) => prompt.overrides.merged(&item.overrides),
+        None => item.overrides.clone(),
+    }
+}
+
+fn read_items(path: &Path) -> Result<Vec<Item>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut items = Vec::new();
+    let mut ids = HashSet::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let item: Item = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: invalid batch item", path.display(), index + 1))?;
+        anyhow::ensure!(
+            ids.insert(item.id.clone()),
+            "{}:{}: duplicate id '{}'",
+            path.display(),
+            index + 1,
+            item.id
+        );
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn read_outcomes(path: &Path) -> Result<Vec<Outcome>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Outcome>(line).ok())
+        .collect())
+}
+
+fn read_done(outcomes: &[Outcome], retry_failed: bool) -> HashSet<String> {
+    outcomes
+        .iter()
+        .filter(|outcome| !retry_failed || outcome.error.is_none())
+        .map(|outcome| outcome.id.clone())
+        .collect()
+}
+
+fn read_failures(outcomes: &[Outcome]) -> HashMap<&str, (usize, &str)> {
+    let mut failures = HashMap::new();
+    for outcome in outcomes {
+        if let Some(ref error) = outcome.error {
+            let entry = failures.entry(outcome.id.as_str()).or_insert((0, ""));
+            *entry = (entry.0 + 1, error.as_str());
+        }
+    }
+    failures
+}
+
+fn open_output(path: &Path) -> Result<File> {
+    let needs_newline = fs::read(path)
+        .map(|content| !content.is_empty() && !content.ends_with(b"\n"))
+        .unwrap_or(false);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    if needs_newline {
+        writeln!(file)?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_items() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("in.jsonl");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"id":"a","input":"x","prompt":"sum"}"#,
+                "\n\n",
+                r#"{"id":"b","input":"y","overrides":{"model":"m","temperature":0.1}}"#,
+                "\n"
+            ),
+        )?;
+        let items = read_items(&path)?;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].prompt.as_deref(), Some("sum"));
+        assert_eq!(items[1].overrides.model.as_deref(), Some("m"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_items_rejects_duplicate_ids() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("in.jsonl");
+        fs::write(
+            &path,
+            "{\"id\":\"a\",\"input\":\"x\"}\n{\"id\":\"a\",\"input\":\"y\"}\n",
+        )?;
+        let err = read_items(&path).unwrap_err();
+        assert!(err.to_string().contains("duplicate id 'a'"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_items_reports_line_number() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("in.jsonl");
+        fs::write(&path, "{\"id\":\"a\",\"input\":\"x\"}\n{\"id\":\"b\"}\n")?;
+        let err = read_items(&path).unwrap_err();
+        assert!(err.to_string().ends_with(":2: invalid batch item"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_done() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("out.jsonl");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"id":"a","text":"ok","usage":{"prompt_tokens":1}}"#,
+                "\n",
+                r#"{"id":"b","error":"boom"}"#,
+                "\n",
+                r#"{"id":"c","te"#
+            ),
+        )?;
+
+        let outcomes = read_outcomes(&path)?;
+        let done = read_done(&outcomes, false);
+        assert_eq!(done, HashSet::from(["a".to_string(), "b".to_string()]));
+
+        let done = read_done(&outcomes, true);
+        assert_eq!(done, HashSet::from(["a".to_string()]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_done_missing_output() -> Result<()> {
+        let dir = tempdir()?;
+        assert!(read_outcomes(&dir.path().join("none.jsonl"))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_failures_counts_attempts() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("out.jsonl");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"id":"a","error":"timeout"}"#,
+                "\n",
+                r#"{"id":"b","text":"ok"}"#,
+                "\n",
+                r#"{"id":"a","error":"rate limit"}"#,
+                "\n"
+            ),
+        )?;
+        let outcomes = read_outcomes(&path)?;
+        assert_eq!(
+            read_failures(&outcomes),
+            HashMap::from([("a", (2, "rate limit"))])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_output_terminates_partial_line() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("out.jsonl");
+        fs::write(&path, "{\"id\":\"a\"")?;
+        let mut file = open_output(&path)?;
+        writeln!(file, "{{\"id\":\"b\"}}")?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "{\"id\":\"a\"\n{\"id\":\"b\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_rejects_prompt_and_prompt_text() {
+        let config = Config {
+            version: crate::config::VERSION,
+            provider: "gemini".to_string(),
+            gemini: None,
+            prompt: std::collections::HashMap::from([("sum".to_string(), "Sum.".to_string())]),
+            ..Default::default()
+        };
+        let mut item: Item =
+            serde_json::from_str(r#"{"id":"a","input":"x","prompt":"sum"}"#).unwrap();
+        let prompt = resolve(&config, &item).unwrap();
+        let rendered = render(
+            &Vars::default(),
+            &Redactor::new(&Default::default()).unwrap(),
+            &item,
+            prompt.as_ref(),
+        )
+        .unwrap();
+        assert!(rendered.starts_with("Sum.\n\nx"));
+
+        item.prompt_text = Some("Other.".to_string());
+        assert!(resolve(&config, &item).is_err());
+    }
+
+    #[test]
+    fn test_render_item_vars_override_global_vars() {
+        let config = Config {
+            version: crate::config::VERSION,
+            provider: "gemini".to_string(),
+            gemini: None,
+            prompt: std::collections::HashMap::new(),
+            ..Default::default()
+        };
+        let item: Item = serde_json::from_str(
+            r#"{"id":"a","input":"x","prompt_text":"Into {{lang}}.","vars":{"lang":"French"}}"#,
+        )
+        .unwrap();
+        let vars = Vars {
+            user: HashMap::from([("lang".to_string(), "German".to_string())]),
+            files: vec![],
+        };
+        let prompt = resolve(&config, &item).unwrap();
+        let rendered = render(
+            &vars,
+            &Redactor::new(&Default::default()).unwrap(),
+            &item,
+            prompt.as_ref(),
+        )
+        .unwrap();
+        assert!(rendered.starts_with("Into French."));
+    }
+
+    #[test]
+    fn test_overrides_layer_item_over_prompt() {
+        let item: Item =
+            serde_json::from_str(r#"{"id":"a","input":"x","overrides":{"temperature":0.5}}"#)
+                .unwrap();
+        let prompt = Prompt {
+            overrides: GeminiOverrides {
+                model: Some("pro".to_string()),
+                temperature: Some(1.0),
+                ..Default::default()
+            },
+            ..Prompt::from_text("p")
+        };
+        let merged = overrides(Some(&prompt), &item);
+        assert_eq!(merged.model.as_deref(), Some("pro"));
+        assert_eq!(merged.temperature, Some(0.5));
+    }
+
+    #[test]
+    fn test_outcome_marks_truncated_response_as_failed() {
+        let response = LlmResponse {
+            text: "partial".to_string(),
+            finish_reason: Some("MAX_TOKENS".to_string()),
+            ..Default::default()
+        };
+        let outcome = Outcome::new("a".to_string(), Ok(response));
+        assert_eq!(outcome.text.as_deref(), Some("partial"));
+        assert!(
+            outcome
+                .error
+                .as_ref()
+                .is_some_and(|e| e.contains("MAX_TOKENS"))
+        );
+        assert_eq!(outcome.kind, Some(ErrorKind::Truncated));
+        assert!(read_done(&[outcome], true).is_empty());
+    }
+
+    #[test]
+    fn test_outcome_serialization_omits_empty_fields() -> Result<()> {
+        let outcome = Outcome {
+            id: "a".to_string(),
+            text: None,
+            usage: None,
+            finish_reason: None,
+            error: Some("boom".to_string()),
+            kind: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&outcome)?,
+            r#"{"id":"a","error":"boom"}"#
+        );
+        Ok(())
+    }
+}
@@ -1,9 +1,22 @@
-use clap::Parser;
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::error::ErrorFormat;
+use crate::llm::DryRun;
+use crate::log::LogFormat;
+use crate::output;
+use crate::redact::RedactMode;
+use crate::usage::GroupBy;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(group(
+    ArgGroup::new("records").conflicts_with_all(["each", "in_place", "diff"])
+))]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(
         short,
         long,
@@ -14,17 +27,372 @@ pub struct Cli {
     #[arg(short, long, help = "Additional message to include after input.")]
     pub message: Option<String>,
 
+    #[arg(
+        long = "var",
+        value_name = "NAME=VALUE",
+        value_parser = crate::template::parse_var,
+        help = "Set a variable used as {{NAME}} in the prompt."
+    )]
+    pub vars: Vec<(String, String)>,
+
     #[arg(
         help = "Files to process. Reads from stdin if no files are provided. Use '-' to read from stdin within a list of files."
     )]
     pub files: Vec<PathBuf>,
 
+    #[arg(
+        long,
+        help = "Process each file as a separate request with the same prompt."
+    )]
+    pub each: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 4,
+        help = "Number of requests to run in parallel when processing files or records separately."
+    )]
+    pub jobs: usize,
+
+    #[arg(
+        long,
+        requires = "each",
+        conflicts_with = "output_suffix",
+        help = "With --each, write each response to a file of the same name in this directory."
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "each",
+        help = "With --each, write each response next to its input file with this suffix appended."
+    )]
+    pub output_suffix: Option<String>,
+
+    #[arg(
+        short = 'o',
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["output_dir", "output_suffix", "in_place", "diff", "records"],
+        help = "Write the response to PATH instead of stdout. {dir}, {name}, {stem} and {ext} in PATH are replaced for each input file, which is then processed separately."
+    )]
+    pub output_file: Option<String>,
+
+    #[arg(
+        long,
+        requires = "output_file",
+        help = "With --output-file, also print the response to stdout."
+    )]
+    pub tee: bool,
+
+    #[arg(
+        short = 'i',
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        conflicts_with_all = ["output_dir", "output_suffix"],
+        help = "Replace each file with the response, keeping a backup if SUFFIX is given."
+    )]
+    pub in_place: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["output_dir", "output_suffix"],
+        help = "Show a unified diff of each file against the response instead of writing it."
+    )]
+    pub diff: bool,
+
+    #[arg(
+        long,
+        group = "records",
+        help = "Treat each input line as a separate request and print one response per line."
+    )]
+    pub lines: bool,
+
+    #[arg(
+        long,
+        group = "records",
+        help = "Like --lines, but with NUL-delimited input and output records."
+    )]
+    pub null: bool,
+
+    #[arg(
+        long,
+        group = "records",
+        help = "Treat each input line as a JSON record and print one JSON result per line."
+    )]
+    pub jsonl: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        conflicts_with_all = ["each", "in_place", "diff", "records"],
+        help = "Print the response as text or as a JSON object with the model, token usage, latency and request id."
+    )]
+    pub output: output::Format,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Fail if connecting to the provider takes longer than MS milliseconds."
+    )]
+    pub connect_timeout: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Fail if the provider sends no data for MS milliseconds."
+    )]
+    pub read_timeout: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Fail if a request takes longer than MS milliseconds in total."
+    )]
+    pub total_timeout: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        help = "What to do when the input contains secrets. Overrides the redact mode from the configuration."
+    )]
+    pub redact: Option<RedactMode>,
+
     #[arg(
         long,
         help = "Create a default configuration file if it doesn't exist."
     )]
     pub init_config: bool,
 
-    #[arg(short, long, help = "Enable verbose output for debugging.")]
-    pub verbose: bool,
+    #[arg(
+        long,
+        requires = "init_config",
+        help = "With --init-config, replace an existing configuration file after backing it up."
+    )]
+    pub force: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "PAIP_CONFIG",
+        help = "Read the user configuration from PATH instead of the default location."
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "init_config",
+        help = "Ignore all configuration files and prompt directories and use the built-in defaults."
+    )]
+    pub no_config: bool,
+
+    #[arg(
+        long,
+        help = "Print the configuration files and prompt directories in use, lowest precedence first."
+    )]
+    pub show_config_sources: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        global = true,
+        help = "Print errors as text or as a JSON object on stderr."
+    )]
+    pub error_format: ErrorFormat,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "request",
+        conflicts_with_all = ["each", "in_place", "diff", "records", "output_file"],
+        help = "Print the request that would be sent, or a curl command for it, without sending it."
+    )]
+    pub dry_run: Option<DryRun>,
+
+    #[arg(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        global = true,
+        help = "Log timings with -v, also request lines and response headers with -vv, and also the full input and request body with -vvv."
+    )]
+    pub verbose: u8,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        global = true,
+        help = "Append log lines to PATH instead of stderr."
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        global = true,
+        help = "Write log lines as text or as JSON objects."
+    )]
+    pub log_format: LogFormat,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "Process a JSONL file of requests, skipping ids already in the output.")]
+    Batch(BatchArgs),
+
+    #[command(about = "List and inspect the available prompts.")]
+    Prompts {
+        #[command(subcommand)]
+        command: PromptsCommand,
+    },
+
+    #[command(about = "Inspect the configuration.")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    #[command(about = "Summarize the recorded requests with their token usage and estimated cost.")]
+    Usage {
+        #[arg(
+            long,
+            value_name = "GROUP",
+            default_value = "day",
+            help = "Group the requests by day, model or prompt."
+        )]
+        by: GroupBy,
+
+        #[arg(
+            long,
+            value_name = "DATE",
+            help = "Only include requests on or after DATE (YYYY-MM-DD, UTC)."
+        )]
+        since: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    #[command(about = "Create a minimal configuration file by answering a few questions.")]
+    Init {
+        #[arg(
+            long,
+            help = "Replace an existing configuration file after backing it up."
+        )]
+        force: bool,
+    },
+
+    #[command(about = "Check the configuration files against the schema and report all problems.")]
+    Validate,
+
+    #[command(about = "Print the effective configuration from all sources with API keys redacted.")]
+    Show,
+
+    #[command(about = "Print one value of the effective configuration.")]
+    Get {
+        #[arg(help = "Dotted key, e.g. gemini.model.")]
+        key: String,
+    },
+
+    #[command(about = "Set a value in the configuration file, keeping its comments and layout.")]
+    Set {
+        #[arg(help = "Dotted key, e.g. gemini.model.")]
+        key: String,
+
+        #[arg(help = "New value, parsed as TOML unless the key holds a string.")]
+        value: String,
+    },
+
+    #[command(
+        about = "Upgrade the configuration file to the current version, converting a legacy config.yaml."
+    )]
+    Migrate,
+}
+
+#[derive(Subcommand)]
+pub enum PromptsCommand {
+    #[command(about = "List prompt names with their description or first line.")]
+    List,
+
+    #[command(about = "Show the resolved text and settings of a prompt.")]
+    Show {
+        #[arg(help = "Name of the prompt.")]
+        name: String,
+    },
+}
+
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct BatchArgs {
+    #[command(subcommand)]
+    pub command: Option<BatchCommand>,
+
+    #[arg(
+        required = true,
+        help = "JSONL file with one request per line: id, input, and optionally prompt, prompt_text, message and overrides."
+    )]
+    pub input: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        required = true,
+        help = "JSONL file to append results to. Ids already present are skipped."
+    )]
+    pub output: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 4,
+        help = "Number of requests to run in parallel."
+    )]
+    pub jobs: usize,
+
+    #[arg(long, help = "Run items again whose previous result was an error.")]
+    pub retry_failed: bool,
+}
+
+#[derive(Subcommand)]
+pub enum BatchCommand {
+    #[command(about = "Submit a JSONL file to the provider's batch API and print the batch name.")]
+    Submit {
+        #[arg(help = "JSONL file in the same format as for 'paip batch'.")]
+        input: PathBuf,
+
+        #[arg(
+            long,
+            help = "Display name of the batch. Defaults to the input file name."
+        )]
+        name: Option<String>,
+    },
+
+    #[command(about = "Show the state of a submitted batch.")]
+    Status {
+        #[arg(help = "Batch name as printed by 'paip batch submit'.")]
+        name: String,
+    },
+
+    #[command(about = "Download the results of a finished batch as JSONL.")]
+    Fetch {
+        #[arg(help = "Batch name as printed by 'paip batch submit'.")]
+        name: String,
+
+        #[arg(short, long, help = "JSONL file to write the results to.")]
+        output: PathBuf,
+    },
+
+    #[command(about = "Cancel a submitted batch.")]
+    Cancel {
+        #[arg(help = "Batch name as printed by 'paip batch submit'.")]
+        name: String,
+    },
 }
@@ -0,0 +1,78 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn today() -> String {
+    date(SystemTime::now())
+}
+
+pub fn date(time: SystemTime) -> String {
+    let (year, month, day) = civil(unix_days(time));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+pub fn timestamp(time: SystemTime) -> String {
+    let millis = unix_millis(time);
+    let seconds = millis.div_euclid(1_000).rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        date(time),
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis.rem_euclid(1_000)
+    )
+}
+
+fn unix_days(time: SystemTime) -> i64 {
+    unix_millis(time).div_euclid(86_400_000)
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+// Converts days since 1970-01-01 to a proleptic Gregorian date, see
+// https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_date_epoch() {
+        assert_eq!(date(UNIX_EPOCH), "1970-01-01");
+    }
+
+    #[test]
+    fn test_date_leap_day() {
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(date(time), "2000-02-29");
+    }
+
+    #[test]
+    fn test_date_end_of_day() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_766_015_999);
+        assert_eq!(date(time), "2025-12-17");
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_766_015_999_042);
+        assert_eq!(timestamp(time), "2025-12-17T23:59:59.042Z");
+    }
+}
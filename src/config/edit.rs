@@ -0,0 +1,179 @@
+use anyhow::{Context, Result, anyhow, bail};
+use std::fs;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, TableLike, Value};
+
+use super::{get_path, schema};
+use crate::output;
+
+pub fn set(path: Option<&Path>, key: &str, value: &str) -> Result<()> {
+    let path = get_path(path)?;
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read configuration file at {}", path.display()))?;
+    let updated = set_value(&content, key, value)?;
+
+    let table: toml::Table = toml::from_str(&updated)
+        .with_context(|| format!("Setting '{key}' would make the configuration unreadable"))?;
+    let problems: Vec<String> = schema::check(&updated, &table, false)?
+        .iter()
+        .map(|p| format!("{}:{}", path.display(), p))
+        .collect();
+    anyhow::ensure!(
+        problems.is_empty(),
+        "Not saving, the configuration would be invalid:\n{}",
+        problems.join("\n")
+    );
+    output::write_atomic(&path, &updated)
+}
+
+fn set_value(content: &str, key: &str, raw: &str) -> Result<String> {
+    let parts: Vec<&str> = key.split('.').collect();
+    let (section, name) = match parts.as_slice() {
+        [name] => (None, *name),
+        [section, name] => (Some(*section), *name),
+        _ => bail!("Unsupported configuration key '{}'", key),
+    };
+    anyhow::ensure!(
+        parts.iter().all(|p| !p.is_empty()),
+        "Invalid configuration key '{}'",
+        key
+    );
+    let rendered = schema::parse_value(&parts, raw)?.to_string();
+    let value: Value = rendered
+        .parse()
+        .map_err(|e| anyhow!("Failed to render '{}': {}", key, e))?;
+
+    let mut document: DocumentMut = content
+        .parse()
+        .map_err(|e| anyhow!("Failed to parse configuration: {}", e))?;
+
+    let table = match section {
+        None => document.as_table_mut() as &mut dyn TableLike,
+        Some(section) => document
+            .entry(section)
+            .or_insert(toml_edit::table())
+            .as_table_like_mut()
+            .ok_or_else(|| anyhow!("'{}' is not a table", section))?,
+    };
+    match table.get_mut(name) {
+        Some(Item::Value(existing)) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        Some(_) => bail!("'{}' is a table", key),
+        None => {
+            table.insert(name, Item::Value(value));
+        }
+    }
+    if let Some(inline) = section
+        .and_then(|section| document.get_mut(section))
+        .and_then(Item::as_inline_table_mut)
+    {
+        inline.fmt();
+    }
+    Ok(document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# my settings
+version = 1
+
+provider = "gemini" # only one for now
+timeout = 90000
+
+[gemini]
+key = "abc"
+model = "gemini-2.5-flash" # fast
+temperature = 1.0
+
+[prompt]
+commit = """
+Write a commit message.
+"""
+"#;
+
+    #[test]
+    fn test_set_value_replaces_in_place() -> Result<()> {
+        let updated = set_value(CONFIG, "gemini.model", "gemini-2.5-pro")?;
+        assert_eq!(
+            updated,
+            CONFIG.replace(
+                "model = \"gemini-2.5-flash\" # fast",
+                "model = \"gemini-2.5-pro\" # fast"
+            )
+        );
+
+        let updated = set_value(CONFIG, "prompt.commit", "Short.")?;
+        assert!(updated.ends_with("[prompt]\ncommit = \"Short.\"\n"));
+
+        let updated = set_value(CONFIG, "timeout", "1000")?;
+        assert!(updated.contains("timeout = 1000\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_value_inserts_missing_keys() -> Result<()> {
+        let updated = set_value(CONFIG, "gemini.top_k", "40")?;
+        assert!(updated.contains("temperature = 1.0\ntop_k = 40\n\n[prompt]"));
+
+        let updated = set_value(CONFIG, "prompt.sum", "Summarize.")?;
+        assert!(updated.ends_with("\"\"\"\nsum = \"Summarize.\"\n"));
+
+        let updated = set_value("version = 1\n", "gemini.model", "m")?;
+        assert_eq!(updated, "version = 1\n\n[gemini]\nmodel = \"m\"\n");
+
+        let updated = set_value("[gemini]\nmodel = \"m\"\n", "provider", "gemini")?;
+        assert_eq!(updated, "provider = \"gemini\"\n[gemini]\nmodel = \"m\"\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_value_in_inline_table() -> Result<()> {
+        let content = "version = 1\ngemini = { key = \"k\", model = \"m\" } # inline\n";
+        let updated = set_value(content, "gemini.temperature", "0.5")?;
+        assert_eq!(
+            updated,
+            "version = 1\ngemini = { key = \"k\", model = \"m\", temperature = 0.5 } # inline\n"
+        );
+
+        let updated = set_value(content, "gemini.model", "gemini-2.5-pro")?;
+        assert!(updated.contains("model = \"gemini-2.5-pro\" }"));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        fs::write(&path, format!("provider = \"gemini\"\n{content}"))?;
+        set(Some(&path), "gemini.temperature", "0.5")?;
+        let config: crate::config::Config = toml::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!(config.gemini.unwrap().temperature, Some(0.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_value_rejects_deep_keys() {
+        assert!(set_value(CONFIG, "a.b.c", "1").is_err());
+        assert!(set_value(CONFIG, "gemini", "1").is_err());
+    }
+
+    #[test]
+    fn test_set_validates_before_saving() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        fs::write(&path, CONFIG)?;
+
+        let err = set(Some(&path), "gemini.temperature", "5").unwrap_err();
+        assert!(err.to_string().contains("must be at most 2"));
+        assert_eq!(fs::read_to_string(&path)?, CONFIG);
+
+        set(Some(&path), "gemini.thinking_level", "low")?;
+        let config: crate::config::Config = toml::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!(
+            config.gemini.unwrap().thinking_level.as_deref(),
+            Some("low")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,439 @@
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{VERSION, backup, get_path, schema};
+use crate::output;
+
+type Step = fn(&str) -> Result<String>;
+
+// STEPS[n] rewrites a file of version n + 1 as version n + 2.
+const STEPS: &[Step] = &[];
+
+pub fn migrate(path: Option<&Path>) -> Result<()> {
+    let path = get_path(path)?;
+
+    if !path.exists() {
+        let legacy = legacy_path(&path)
+            .ok_or_else(|| anyhow!("No configuration file found at {}", path.display()))?;
+        let yaml = fs::read_to_string(&legacy)
+            .with_context(|| format!("Failed to read {}", legacy.display()))?;
+        let content = upgrade(&from_yaml(&yaml)?)?;
+        check(&path, &content)?;
+        output::write_atomic(&path, &content)?;
+        println!("Converted {} to {}", legacy.display(), path.display());
+        return Ok(());
+    }
+
+    let original =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let content = upgrade(&original)?;
+    if content == original {
+        println!("{} is already at version {}", path.display(), VERSION);
+        return Ok(());
+    }
+    check(&path, &content)?;
+    let backup = backup(&path)?;
+    output::write_atomic(&path, &content)?;
+    println!(
+        "Migrated {} to version {}, the original was saved to {}",
+        path.display(),
+        VERSION,
+        backup.display()
+    );
+    Ok(())
+}
+
+fn legacy_path(path: &Path) -> Option<PathBuf> {
+    ["yaml", "yml"]
+        .into_iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|p| p.is_file())
+}
+
+fn upgrade(content: &str) -> Result<String> {
+    let table: toml::Table = toml::from_str(content).context("Failed to parse configuration")?;
+    let version = table
+        .get("version")
+        .and_then(|v| v.as_integer())
+        .ok_or_else(|| anyhow!("Configuration file has no version"))?;
+    anyhow::ensure!(
+        (1..=i64::from(VERSION)).contains(&version),
+        "Cannot migrate configuration version {}, this version of paip supports 1 to {}",
+        version,
+        VERSION
+    );
+
+    let mut content = content.to_string();
+    for step in &STEPS[version as usize - 1..] {
+        content = step(&content)?;
+    }
+    Ok(content)
+}
+
+fn check(path: &Path, content: &str) -> Result<()> {
+    let table: toml::Table = toml::from_str(content)?;
+    for problem in schema::check(content, &table, false)? {
+        eprintln!("Warning: {}:{}", path.display(), problem);
+    }
+    Ok(())
+}
+
+fn from_yaml(yaml: &str) -> Result<String> {
+    let mut root: Vec<String> = Vec::new();
+    let mut tables: Vec<String> = Vec::new();
+    let mut pending: Vec<String> = Vec::new();
+    let mut in_section = false;
+    let lines: Vec<&str> = yaml.lines().collect();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let number = i + 1;
+        i += 1;
+
+        let trimmed = line.trim();
+        if trimmed == "---" {
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            pending.push(trimmed.to_string());
+            continue;
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        let unsupported = || anyhow!("Unsupported YAML at line {}: {}", number, trimmed);
+        let (key, rest) = split_key(trimmed).ok_or_else(unsupported)?;
+        let key = toml_key(key);
+
+        if indent == 0 && rest.is_empty() {
+            in_section = true;
+            tables.append(&mut pending);
+            if tables.last().is_some_and(|l| !l.is_empty()) {
+                tables.push(String::new());
+            }
+            tables.push(format!("[{key}]"));
+            continue;
+        }
+        if rest.is_empty() || (indent > 0 && !in_section) {
+            return Err(unsupported());
+        }
+        if indent == 0 {
+            in_section = false;
+        }
+
+        let (value, comment) = match block_header(rest) {
+            Some(header) => {
+                let end = block_end(&lines, i, indent);
+                let value = block(header, &lines[i..end]);
+                i = end;
+                (Some(value), None)
+            }
+            None => scalar(rest).ok_or_else(unsupported)?,
+        };
+
+        let target = if in_section { &mut tables } else { &mut root };
+        target.append(&mut pending);
+        if key == "version" && !in_section {
+            continue;
+        }
+        let Some(value) = value else {
+            continue;
+        };
+        let comment = comment.map(|c| format!(" {c}")).unwrap_or_default();
+        target.push(format!("{key} = {value}{comment}"));
+    }
+
+    let target = if in_section { &mut tables } else { &mut root };
+    target.append(&mut pending);
+
+    let first_key = root
+        .iter()
+        .position(|l| !l.is_empty() && !l.starts_with('#'))
+        .unwrap_or(root.len());
+    root.insert(first_key, "version = 1".to_string());
+
+    let mut content = root.join("\n");
+    if !tables.is_empty() {
+        content.push('\n');
+        content.push_str(&tables.join("\n"));
+    }
+    content.push('\n');
+    Ok(content)
+}
+
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with("- ") || line == "-" {
+        return None;
+    }
+    let search_from = match line.chars().next()? {
+        quote @ ('"' | '\'') => line[1..].find(quote)? + 2,
+        _ => 0,
+    };
+    let colon = line[search_from..]
+        .match_indices(':')
+        .map(|(p, _)| search_from + p)
+        .find(|&p| line[p + 1..].is_empty() || line[p + 1..].starts_with(' '))?;
+    let rest = line[colon + 1..].trim();
+    let rest = if rest.starts_with('#') { "" } else { rest };
+    Some((line[..colon].trim(), rest))
+}
+
+pub(super) fn toml_key(key: &str) -> String {
+    let key = key
+        .strip_prefix('"')
+        .and_then(|k| k.strip_suffix('"'))
+        .or_else(|| key.strip_prefix('\'').and_then(|k| k.strip_suffix('\'')))
+        .unwrap_or(key);
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return key.to_string();
+    }
+    toml::Value::String(key.to_string()).to_string()
+}
+
+fn block_header(value: &str) -> Option<&str> {
+    let header = value.split(" #").next().unwrap_or_default().trim();
+    let mut chars = header.chars();
+    let style = chars.next()?;
+    let chomping = chars.as_str();
+    ((style == '|' || style == '>') && matches!(chomping, "" | "-" | "+")).then_some(header)
+}
+
+fn block_end(lines: &[&str], start: usize, indent: usize) -> usize {
+    let mut end = start;
+    for (j, line) in lines.iter().enumerate().skip(start) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.len() - line.trim_start().len() <= indent {
+            break;
+        }
+        end = j + 1;
+    }
+    end
+}
+
+fn block(header: &str, body: &[&str]) -> String {
+    let indent = body
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or_default();
+    let lines: Vec<&str> = body
+        .iter()
+        .map(|l| {
+            if l.trim().is_empty() {
+                ""
+            } else {
+                &l[indent..]
+            }
+        })
+        .collect();
+
+    let mut text = String::new();
+    if header.starts_with('>') {
+        let mut after_break = true;
+        for line in lines {
+            if line.is_empty() {
+                text.push('\n');
+                after_break = true;
+                continue;
+            }
+            if !after_break {
+                text.push(' ');
+            }
+            text.push_str(line);
+            after_break = false;
+        }
+    } else {
+        text = lines.join("\n");
+    }
+
+    let text = match header.chars().nth(1) {
+        Some('-') => text.trim_end_matches('\n').to_string(),
+        Some('+') => format!("{text}\n"),
+        _ => format!("{}\n", text.trim_end_matches('\n')),
+    };
+    toml::Value::String(text).to_string()
+}
+
+fn scalar(text: &str) -> Option<(Option<String>, Option<String>)> {
+    let (value, rest) = match text.chars().next()? {
+        '"' => {
+            let end = double_quoted_end(text)?;
+            let mut table: toml::Table = toml::from_str(&format!("v = {}", &text[..=end])).ok()?;
+            (Some(table.remove("v")?.to_string()), &text[end + 1..])
+        }
+        '\'' => {
+            let end = single_quoted_end(text)?;
+            let value = text[1..end].replace("''", "'");
+            (
+                Some(toml::Value::String(value).to_string()),
+                &text[end + 1..],
+            )
+        }
+        '[' | '{' | '&' | '*' | '!' | '|' | '>' => return None,
+        _ => match text.find(" #") {
+            Some(p) => (plain(text[..p].trim()), &text[p..]),
+            None => (plain(text), ""),
+        },
+    };
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return None;
+    }
+    Some((value, (!rest.is_empty()).then(|| rest.to_string())))
+}
+
+fn double_quoted_end(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (p, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(p),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn single_quoted_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut p = 1;
+    while p < bytes.len() {
+        if bytes[p] == b'\'' {
+            if bytes.get(p + 1) == Some(&b'\'') {
+                p += 2;
+                continue;
+            }
+            return Some(p);
+        }
+        p += 1;
+    }
+    None
+}
+
+fn plain(text: &str) -> Option<String> {
+    match text {
+        "" | "~" | "null" => return None,
+        "true" | "false" => return Some(text.to_string()),
+        _ => {}
+    }
+    if let Ok(integer) = text.parse::<i64>() {
+        return Some(integer.to_string());
+    }
+    if text.contains(['.', 'e', 'E'])
+        && text.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+' || c == '.')
+        && let Ok(float) = text.parse::<f64>()
+    {
+        return Some(toml::Value::Float(float).to_string());
+    }
+    Some(toml::Value::String(text.to_string()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const LEGACY: &str = r#"# paip configuration
+provider: gemini
+timeout: 30000 # milliseconds
+
+gemini:
+  key: "abc"
+  model: gemini-2.5-flash
+  temperature: 1.0
+  top_k: 64
+
+prompt:
+  sum: Summarize the following.
+  # commit messages
+  commit: |
+    Write a conventional commit message.
+
+    type(scope): description
+
+  quote: 'It''s fine'
+  folded: >-
+    one
+    two
+
+    three
+"#;
+
+    #[test]
+    fn test_from_yaml() -> Result<()> {
+        let content = from_yaml(LEGACY)?;
+        assert_eq!(
+            content,
+            r#"# paip configuration
+version = 1
+provider = "gemini"
+timeout = 30000 # milliseconds
+
+[gemini]
+key = "abc"
+model = "gemini-2.5-flash"
+temperature = 1.0
+top_k = 64
+
+[prompt]
+sum = "Summarize the following."
+# commit messages
+commit = """
+Write a conventional commit message.
+
+type(scope): description
+"""
+
+quote = "It's fine"
+folded = """
+one two
+three"""
+"#
+        );
+
+        let config: Config = toml::from_str(&content)?;
+        assert_eq!(config.timeouts.total_timeout, Some(30000));
+        assert_eq!(config.prompt["folded"], "one two\nthree");
+        assert_eq!(config.prompt["quote"], "It's fine");
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_yaml_rejects_nested_sections() {
+        let err = from_yaml("gemini:\n  extra:\n    a: 1\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+        assert!(from_yaml("prompt:\n  - a\n").is_err());
+    }
+
+    #[test]
+    fn test_upgrade_current_version_is_unchanged() -> Result<()> {
+        let content = include_str!("../../config.toml");
+        assert_eq!(upgrade(content)?, content);
+        assert!(upgrade("version = 99").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_converts_legacy_yaml() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        fs::write(dir.path().join("config.yaml"), LEGACY)?;
+
+        migrate(Some(&path))?;
+        let config: Config = toml::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!(config.version, VERSION);
+        assert!(config.prompt["commit"].starts_with("Write a conventional"));
+
+        migrate(Some(&path))?;
+        assert!(!dir.path().join("config.toml.bak").exists());
+        Ok(())
+    }
+}
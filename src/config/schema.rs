@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use serde_json::Value as Schema;
+use std::fmt;
+use toml::de::{DeTable, DeValue};
+
+const SCHEMA: &str = include_str!("../../config.schema.json");
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+pub fn check(content: &str, table: &toml::Table, partial: bool) -> Result<Vec<Problem>> {
+    let schema: Schema = serde_json::from_str(SCHEMA).context("Invalid embedded schema")?;
+    let mut found = Vec::new();
+    check_table(&schema, table, &mut Vec::new(), partial, &mut found);
+
+    let document = DeTable::parse(content).ok();
+    let mut problems: Vec<Problem> = found
+        .into_iter()
+        .map(|(path, message)| {
+            let offset = document
+                .as_ref()
+                .and_then(|d| locate(d.get_ref(), &path))
+                .unwrap_or_default();
+            let (line, column) = position(content, offset);
+            Problem {
+                line,
+                column,
+                message,
+            }
+        })
+        .collect();
+    problems.sort_by_key(|p| (p.line, p.column));
+    Ok(problems)
+}
+
+pub fn parse_value(path: &[&str], raw: &str) -> Result<toml::Value> {
+    let schema: Schema = serde_json::from_str(SCHEMA).context("Invalid embedded schema")?;
+    let property = path.iter().try_fold(&schema, |schema, part| {
+        schema["properties"]
+            .get(*part)
+            .or_else(|| schema.get("additionalProperties").filter(|a| a.is_object()))
+    });
+    if property.is_some_and(|p| p["type"] == "string") {
+        return Ok(toml::Value::String(raw.to_string()));
+    }
+    let parsed = toml::from_str::<toml::Table>(&format!("v = {raw}"))
+        .ok()
+        .and_then(|mut table| table.remove("v"));
+    Ok(parsed.unwrap_or_else(|| toml::Value::String(raw.to_string())))
+}
+
+fn check_value(
+    schema: &Schema,
+    value: &toml::Value,
+    path: &mut Vec<String>,
+    partial: bool,
+    found: &mut Vec<(Vec<String>, String)>,
+) {
+    let name = path.join(".");
+    let expected = schema["type"].as_str().unwrap_or_default();
+    let matches = match expected {
+        "object" => value.is_table(),
+        "string" => value.is_str(),
+        "integer" => value.is_integer(),
+        "number" => value.is_integer() || value.is_float(),
+        "boolean" => value.is_bool(),
+        "array" => value.is_array(),
+        _ => true,
+    };
+    if !matches {
+        found.push((
+            path.clone(),
+            format!(
+                "'{name}' must be of type {expected}, found {}",
+                value.type_str()
+            ),
+        ));
+        return;
+    }
+
+    if let Some(options) = schema["enum"].as_array()
+        && let Some(text) = value.as_str()
+        && !options.iter().any(|o| o.as_str() == Some(text))
+    {
+        let options: Vec<&str> = options.iter().filter_map(|o| o.as_str()).collect();
+        found.push((
+            path.clone(),
+            format!(
+                "'{name}' must be one of {}, found '{text}'",
+                options.join(", ")
+            ),
+        ));
+    }
+
+    let number = value
+        .as_float()
+        .or_else(|| value.as_integer().map(|i| i as f64));
+    if let Some(number) = number {
+        if let Some(minimum) = schema["minimum"].as_f64()
+            && number < minimum
+        {
+            found.push((
+                path.clone(),
+                format!("'{name}' must be at least {minimum}, found {number}"),
+            ));
+        }
+        if let Some(maximum) = schema["maximum"].as_f64()
+            && number > maximum
+        {
+            found.push((
+                path.clone(),
+                format!("'{name}' must be at most {maximum}, found {number}"),
+            ));
+        }
+    }
+
+    if let Some(table) = value.as_table() {
+        check_table(schema, table, path, partial, found);
+    }
+
+    if let Some(items) = value.as_array()
+        && let Some(item_schema) = schema.get("items")
+    {
+        for (index, item) in items.iter().enumerate() {
+            path.push(index.to_string());
+            check_value(item_schema, item, path, partial, found);
+            path.pop();
+        }
+    }
+}
+
+fn check_table(
+    schema: &Schema,
+    table: &toml::Table,
+    path: &mut Vec<String>,
+    partial: bool,
+    found: &mut Vec<(Vec<String>, String)>,
+) {
+    let section = match path.is_empty() {
+        true => String::new(),
+        false => format!(" in [{}]", path.join(".")),
+    };
+    if !partial {
+        for key in schema["required"].as_array().into_iter().flatten() {
+            if let Some(key) = key.as_str()
+                && !table.contains_key(key)
+            {
+                found.push((
+                    path.clone(),
+                    format!("missing required key '{key}'{section}"),
+                ));
+            }
+        }
+    }
+
+    for (key, value) in table {
+        path.push(key.clone());
+        match schema["properties"].get(key) {
+            Some(property) => check_value(property, value, path, partial, found),
+            None => match schema.get("additionalProperties") {
+                Some(Schema::Bool(false)) => {
+                    found.push((path.clone(), format!("unknown key '{key}'{section}")))
+                }
+                Some(additional) if additional.is_object() => {
+                    check_value(additional, value, path, partial, found)
+                }
+                _ => {}
+            },
+        }
+        path.pop();
+    }
+}
+
+fn locate(table: &DeTable, path: &[String]) -> Option<usize> {
+    let (first, rest) = path.split_first()?;
+    let (key, value) = table.iter().find(|(k, _)| k.get_ref().as_ref() == first)?;
+    if rest.is_empty() {
+        return Some(key.span().start);
+    }
+    match value.get_ref() {
+        DeValue::Table(table) => locate(table, rest).or(Some(key.span().start)),
+        _ => Some(key.span().start),
+    }
+}
+
+fn position(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(content: &str, partial: bool) -> Vec<String> {
+        let table: toml::Table = toml::from_str(content).unwrap();
+        check(content, &table, partial)
+            .unwrap()
+            .iter()
+            .map(|p| p.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_default_config_has_no_problems() {
+        assert!(problems(include_str!("../../config.toml"), false).is_empty());
+    }
+
+    #[test]
+    fn test_reports_all_problems_with_positions() {
+        let content = "version = 1\nprovider = \"openai\"\ntimeout = 1000\n\n[gemini]\nmodel = \"m\"\ntemperature = 3\nthinking_level = \"extreme\"\ntop_kk = 4\n";
+        assert_eq!(
+            problems(content, false),
+            vec![
+                "2:1: 'provider' must be one of gemini, found 'openai'",
+                "7:1: 'gemini.temperature' must be at most 2, found 3",
+                "8:1: 'gemini.thinking_level' must be one of minimal, low, medium, high, found 'extreme'",
+                "9:1: unknown key 'top_kk' in [gemini]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_types_and_missing_keys() {
+        let content = "version = \"1\"\n\n[gemini]\ntemperature = 0.5\n\n[prompt]\nsum = 3\n";
+        assert_eq!(
+            problems(content, false),
+            vec![
+                "1:1: missing required key 'provider'",
+                "1:1: 'version' must be of type integer, found string",
+                "3:2: missing required key 'model' in [gemini]",
+                "7:1: 'prompt.sum' must be of type string, found integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_checks_array_items() {
+        let content = "[redact]\nmode = \"strict\"\npatterns = [\"sk-\\\\w+\", 3]\n";
+        assert_eq!(
+            problems(content, true),
+            vec![
+                "2:1: 'redact.mode' must be one of warn, redact, block, found 'strict'",
+                "3:1: 'redact.patterns.1' must be of type string, found integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_value_follows_schema() -> Result<()> {
+        assert_eq!(
+            parse_value(&["gemini", "temperature"], "0.5")?,
+            toml::Value::Float(0.5)
+        );
+        assert_eq!(
+            parse_value(&["gemini", "model"], "1.5")?,
+            toml::Value::String("1.5".to_string())
+        );
+        assert_eq!(
+            parse_value(&["prompt", "sum"], "true")?,
+            toml::Value::String("true".to_string())
+        );
+        assert_eq!(
+            parse_value(&["unknown"], "plain text")?,
+            toml::Value::String("plain text".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_skips_required_keys() {
+        assert!(problems("[gemini]\nmodel = \"m\"\n", true).is_empty());
+    }
+}
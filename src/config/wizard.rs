@@ -0,0 +1,140 @@
+use anyhow::{Result, anyhow, bail};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use super::{DEFAULT_CONFIG, VERSION, backup, ensure_replaceable, get_path, load_defaults};
+use crate::output;
+
+pub fn init_interactive(path: Option<&Path>, force: bool) -> Result<()> {
+    let path = get_path(path)?;
+    ensure_replaceable(&path, force)?;
+
+    let content = ask(&mut io::stdin().lock(), &mut io::stderr())?;
+    if path.exists() {
+        let backup = backup(&path)?;
+        println!("Existing config file saved to: {}", backup.display());
+    }
+    output::write_atomic(&path, &content)?;
+    println!("Config file created at: {}", path.display());
+    Ok(())
+}
+
+fn ask(input: &mut impl BufRead, output: &mut impl Write) -> Result<String> {
+    let defaults = load_defaults()?;
+    let default_model = defaults.gemini.map(|g| g.model).unwrap_or_default();
+
+    let provider = question(input, output, "Provider", Some("gemini"))?;
+    if provider != "gemini" {
+        bail!("Unsupported LLM provider: {}", provider);
+    }
+
+    writeln!(
+        output,
+        "The API key can come from the GEMINI_API_KEY environment variable (env), a file (file), the output of a command (command) or the config file itself (inline)."
+    )?;
+    let source = question(input, output, "Key source", Some("env"))?;
+    let key = match source.as_str() {
+        "env" => None,
+        "file" => Some(("key_file", question(input, output, "Key file", None)?)),
+        "command" => Some(("key_command", question(input, output, "Key command", None)?)),
+        "inline" => Some(("key", question(input, output, "API key", None)?)),
+        _ => return Err(anyhow!("Unknown key source '{}'", source)),
+    };
+    let model = question(input, output, "Model", Some(&default_model))?;
+
+    let mut content = String::new();
+    if let Some(schema) = DEFAULT_CONFIG
+        .lines()
+        .next()
+        .filter(|l| l.starts_with("#:schema"))
+    {
+        content.push_str(&format!("{schema}\n\n"));
+    }
+    content.push_str(&format!(
+        "version = {VERSION}\n\nprovider = {}\n",
+        quote(&provider)
+    ));
+    let timeouts = toml::to_string(&defaults.timeouts)?;
+    content.push_str(&format!("{timeouts}\n[gemini]\n"));
+    if let Some((name, value)) = key {
+        content.push_str(&format!("{name} = {}\n", quote(&value)));
+    }
+    content.push_str(&format!("model = {}\n", quote(&model)));
+    Ok(content)
+}
+
+fn question(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    label: &str,
+    default: Option<&str>,
+) -> Result<String> {
+    loop {
+        match default {
+            Some(default) => write!(output, "{label} [{default}]: ")?,
+            None => write!(output, "{label}: ")?,
+        }
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            bail!("No answer given for '{}'", label);
+        }
+        let answer = line.trim();
+        if !answer.is_empty() {
+            return Ok(answer.to_string());
+        }
+        if let Some(default) = default {
+            return Ok(default.to_string());
+        }
+    }
+}
+
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::io::Cursor;
+
+    fn answer(input: &str) -> Result<String> {
+        ask(&mut Cursor::new(input), &mut Vec::new())
+    }
+
+    #[test]
+    fn test_ask_key_file_with_defaults() -> Result<()> {
+        let content = answer("\nfile\n\n/home/me/.gemini-key\n\n")?;
+        let config: Config = toml::from_str(&content)?;
+        let gemini = config.gemini.unwrap();
+        assert_eq!(config.version, VERSION);
+        assert_eq!(config.provider, "gemini");
+        assert_eq!(
+            gemini.key_file.as_deref(),
+            Some(Path::new("/home/me/.gemini-key"))
+        );
+        assert_eq!(gemini.model, load_defaults()?.gemini.unwrap().model);
+        assert!(content.starts_with("#:schema "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ask_env_key_and_model() -> Result<()> {
+        let content = answer("gemini\nenv\ngemini-2.5-pro\n")?;
+        let config: Config = toml::from_str(&content)?;
+        let gemini = config.gemini.unwrap();
+        assert!(gemini.key.is_empty());
+        assert!(gemini.key_file.is_none());
+        assert_eq!(gemini.model, "gemini-2.5-pro");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ask_rejects_unknown_answers() {
+        assert!(answer("openai\n").is_err());
+        assert!(answer("\nvault\n").is_err());
+        assert!(answer("\ninline\n").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Other,
+    Config,
+    PromptNotFound,
+    Input,
+    Network,
+    Auth,
+    RateLimit,
+    Safety,
+    Truncated,
+    InvalidOutput,
+    Budget,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Config => 3,
+            ErrorKind::PromptNotFound => 4,
+            ErrorKind::Input => 5,
+            ErrorKind::Network => 6,
+            ErrorKind::Auth => 7,
+            ErrorKind::RateLimit => 8,
+            ErrorKind::Safety => 9,
+            ErrorKind::Truncated => 10,
+            ErrorKind::InvalidOutput => 11,
+            ErrorKind::Budget => 12,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug)]
+struct Classified {
+    kind: ErrorKind,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for Classified {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error
+            .chain()
+            .nth(1)
+            .map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+pub trait Classify<T> {
+    fn classify(self, kind: ErrorKind) -> Result<T>;
+}
+
+impl<T> Classify<T> for Result<T> {
+    fn classify(self, kind: ErrorKind) -> Result<T> {
+        self.map_err(|error| classify(error, kind))
+    }
+}
+
+pub fn classify(error: anyhow::Error, kind: ErrorKind) -> anyhow::Error {
+    if find(&error).is_some() {
+        return error;
+    }
+    anyhow::Error::new(Classified { kind, error })
+}
+
+pub fn failures(kinds: &[ErrorKind], total: usize, items: &str) -> Result<()> {
+    let Some(&first) = kinds.first() else {
+        return Ok(());
+    };
+    let error = anyhow::anyhow!("{} of {total} {items} failed", kinds.len());
+    if kinds.iter().all(|&kind| kind == first) {
+        return Err(classify(error, first));
+    }
+    Err(error)
+}
+
+pub fn kind(error: &anyhow::Error) -> ErrorKind {
+    find(error).unwrap_or(ErrorKind::Other)
+}
+
+fn find(error: &anyhow::Error) -> Option<ErrorKind> {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<Classified>())
+        .map(|c| c.kind)
+}
+
+pub fn report(error: &anyhow::Error, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Text => eprintln!("Error: {error:?}"),
+        ErrorFormat::Json => {
+            let kind = kind(error);
+            let object = serde_json::json!({
+                "error": {
+                    "kind": kind,
+                    "exit_code": kind.exit_code(),
+                    "message": format!("{error:#}"),
+                }
+            });
+            eprintln!("{object}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+
+    #[test]
+    fn test_classify_keeps_message_and_causes() {
+        let error = Err::<(), _>(anyhow!("connection refused"))
+            .context("Failed to reach provider")
+            .classify(ErrorKind::Network)
+            .unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Failed to reach provider: connection refused"
+        );
+        assert_eq!(error.chain().count(), 2);
+        assert_eq!(kind(&error), ErrorKind::Network);
+    }
+
+    #[test]
+    fn test_innermost_classification_wins() {
+        let error = Err::<(), _>(anyhow!("429"))
+            .classify(ErrorKind::RateLimit)
+            .context("Request failed")
+            .classify(ErrorKind::Network)
+            .unwrap_err();
+        assert_eq!(kind(&error), ErrorKind::RateLimit);
+        assert_eq!(format!("{error:#}"), "Request failed: 429");
+    }
+
+    #[test]
+    fn test_unclassified_errors() {
+        let error = anyhow!("boom");
+        assert_eq!(kind(&error), ErrorKind::Other);
+        assert_eq!(kind(&error).exit_code(), 1);
+    }
+
+    #[test]
+    fn test_failures_keep_a_shared_kind() {
+        assert!(failures(&[], 3, "files").is_ok());
+
+        let error =
+            failures(&[ErrorKind::Truncated, ErrorKind::Truncated], 3, "files").unwrap_err();
+        assert_eq!(error.to_string(), "2 of 3 files failed");
+        assert_eq!(kind(&error).exit_code(), 10);
+
+        let error = failures(&[ErrorKind::Truncated, ErrorKind::Network], 3, "files").unwrap_err();
+        assert_eq!(kind(&error), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_kind_serialization() -> Result<()> {
+        assert_eq!(
+            serde_json::to_string(&ErrorKind::PromptNotFound)?,
+            "\"prompt_not_found\""
+        );
+        Ok(())
+    }
+}
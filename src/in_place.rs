@@ -0,0 +1,141 @@
+use anyhow::{Context, Result, anyhow};
+use similar::TextDiff;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{self, ErrorKind};
+use crate::llm::LlmResponse;
+use crate::output;
+
+pub fn prepare(response: &LlmResponse, original: &str) -> Result<String> {
+    if response.is_truncated() {
+        return Err(error::classify(
+            anyhow!("Response was truncated (MAX_TOKENS), refusing to rewrite"),
+            ErrorKind::Truncated,
+        ));
+    }
+
+    let content = strip_fences(&response.text);
+    anyhow::ensure!(
+        !content.trim().is_empty(),
+        "Response was empty, refusing to rewrite"
+    );
+
+    let mut content = content.trim_end_matches('\n').to_string();
+    if original.ends_with('\n') {
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+pub fn strip_fences(text: &str) -> &str {
+    let trimmed = text.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return text;
+    };
+    let Some((_, body)) = rest.split_once('\n') else {
+        return text;
+    };
+    let Some(body) = body.strip_suffix("```") else {
+        return text;
+    };
+    body.strip_suffix('\n').unwrap_or(body)
+}
+
+pub fn apply(path: &Path, content: &str, backup_suffix: Option<&str>) -> Result<()> {
+    if let Some(suffix) = backup_suffix {
+        let path_backup = backup_path(path, suffix);
+        fs::copy(path, &path_backup)
+            .with_context(|| format!("Failed to create backup {}", path_backup.display()))?;
+    }
+    output::write_atomic(path, content)
+}
+
+pub fn diff(path: &Path, original: &str, content: &str) -> String {
+    let name = path.display().to_string();
+    TextDiff::from_lines(original, content)
+        .unified_diff()
+        .header(&format!("a/{name}"), &format!("b/{name}"))
+        .to_string()
+}
+
+fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut path_backup = OsString::from(path.as_os_str());
+    path_backup.push(suffix);
+    PathBuf::from(path_backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn response(text: &str, finish_reason: Option<&str>) -> LlmResponse {
+        LlmResponse {
+            text: text.to_string(),
+            finish_reason: finish_reason.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_strip_fences_with_language() {
+        let text = "```rust\nfn main() {}\n```\n";
+        assert_eq!(strip_fences(text), "fn main() {}");
+    }
+
+    #[test]
+    fn test_strip_fences_without_fences() {
+        let text = "fn main() {}\n";
+        assert_eq!(strip_fences(text), text);
+    }
+
+    #[test]
+    fn test_strip_fences_unterminated() {
+        let text = "```rust\nfn main() {}\n";
+        assert_eq!(strip_fences(text), text);
+    }
+
+    #[test]
+    fn test_prepare_refuses_truncated() {
+        let err = prepare(&response("partial", Some("MAX_TOKENS")), "").unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+        assert_eq!(error::kind(&err), ErrorKind::Truncated);
+    }
+
+    #[test]
+    fn test_prepare_refuses_empty() {
+        let result = prepare(&response("```\n\n```", Some("STOP")), "");
+        assert!(result.unwrap_err().to_string().contains("empty"));
+    }
+
+    #[test]
+    fn test_prepare_matches_trailing_newline() -> Result<()> {
+        let res = response("```\na\nb\n```", Some("STOP"));
+        assert_eq!(prepare(&res, "x\n")?, "a\nb\n");
+        assert_eq!(prepare(&res, "x")?, "a\nb");
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_with_backup() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("file.rs");
+        fs::write(&path, "old\n")?;
+
+        apply(&path, "new\n", Some(".bak"))?;
+        assert_eq!(fs::read_to_string(&path)?, "new\n");
+        assert_eq!(fs::read_to_string(dir.path().join("file.rs.bak"))?, "old\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_unified() {
+        let result = diff(Path::new("f.txt"), "a\nb\n", "a\nc\n");
+        assert!(result.contains("--- a/f.txt"));
+        assert!(result.contains("+++ b/f.txt"));
+        assert!(result.contains("-b\n"));
+        assert!(result.contains("+c\n"));
+    }
+}
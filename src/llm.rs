@@ -1,9 +1,38 @@
-use anyhow::{Result, anyhow};
-use reqwest::blocking::Client;
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use reqwest::blocking::{Client, Request, RequestBuilder};
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Identity, NoProxy, Proxy, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::config::{Config, GeminiConfig};
+use crate::config::{Config, GeminiConfig, GeminiOverrides, KEY_PLACEHOLDER, Timeouts};
+use crate::error::{self, Classify, ErrorKind};
+use crate::log::{self, Logger};
+use crate::usage::Ledger;
+
+mod batch;
+
+pub use batch::{BatchEntry, BatchResult};
+
+const API_KEY_HEADER: &str = "x-goog-api-key";
+const BLOCK_REASONS: [&str; 6] = [
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+];
+const PROXY_VARS: [&str; 4] = ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"];
+const KEY_VAR: &str = "GEMINI_API_KEY";
+const GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com";
 
 #[derive(Debug, Clone, Copy)]
 pub enum LlmProvider {
@@ -18,54 +47,410 @@ impl LlmProvider {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum DryRun {
+    Request,
+    Curl,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LlmResponse {
+    pub text: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+    pub request_id: Option<String>,
+    pub latency: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub output_tokens: u32,
+    pub thinking_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl LlmResponse {
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("MAX_TOKENS")
+    }
+
+    pub fn ensure_complete(&self) -> Result<()> {
+        if self.is_truncated() {
+            return Err(error::classify(
+                anyhow!("Response was truncated (MAX_TOKENS), raise max_output_tokens"),
+                ErrorKind::Truncated,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct LlmClient {
     provider: LlmProvider,
     api_key: String,
+    base_url: String,
     client: Client,
     config: Config,
-    verbose: bool,
+    logger: Logger,
+    ledger: Option<Ledger>,
+    prompt_name: Option<String>,
 }
 
 impl LlmClient {
-    pub fn new(config: &Config, verbose: bool) -> Result<Self> {
-        let (provider, api_key) = match config.provider.as_str() {
-            "gemini" => {
-                let key = config
-                    .gemini
-                    .as_ref()
-                    .ok_or_else(|| anyhow!("Gemini configuration not found for provider 'gemini'"))?
-                    .key
-                    .clone();
-                (LlmProvider::Gemini, key)
-            }
-            _ => return Err(anyhow!("Unsupported LLM provider: {}", config.provider)),
+    pub fn new(config: &Config, logger: &Logger) -> Result<Self> {
+        let client = Self::without_key(config, logger)?;
+        let api_key = match client.provider {
+            LlmProvider::Gemini => config
+                .gemini
+                .as_ref()
+                .ok_or_else(|| anyhow!("Gemini configuration not found"))?
+                .api_key()
+                .classify(ErrorKind::Auth)?,
         };
+        client.with_key(api_key)
+    }
 
-        if api_key.is_empty() || api_key == "YOUR_GEMINI_API_KEY" {
-            return Err(anyhow!(
-                "API key is not configured for provider: {}",
-                provider.as_str()
+    pub fn with_key(mut self, api_key: String) -> Result<Self> {
+        if api_key.is_empty() || api_key == KEY_PLACEHOLDER {
+            return Err(error::classify(
+                anyhow!(
+                    "API key is not configured for provider: {}",
+                    self.provider.as_str()
+                ),
+                ErrorKind::Auth,
             ));
         }
+        self.api_key = api_key;
+        Ok(self)
+    }
 
-        let client = Client::builder()
-            .timeout(Duration::from_millis(config.timeout.into()))
-            .build()?;
+    pub fn without_key(config: &Config, logger: &Logger) -> Result<Self> {
+        let (provider, base_url) = match config.provider.as_str() {
+            "gemini" => {
+                let gemini = config.gemini.as_ref().ok_or_else(|| {
+                    anyhow!("Gemini configuration not found for provider 'gemini'")
+                })?;
+                let base_url = gemini.base_url.as_deref().unwrap_or(GEMINI_BASE_URL);
+                (LlmProvider::Gemini, base_url)
+            }
+            _ => return Err(anyhow!("Unsupported LLM provider: {}", config.provider)),
+        };
+
+        let client = http_client(config)?;
 
         Ok(Self {
             provider,
-            api_key,
+            api_key: String::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
             client,
             config: config.clone(),
-            verbose,
+            logger: logger.clone(),
+            ledger: None,
+            prompt_name: None,
         })
     }
 
-    pub fn send_request(&self, prompt: &str) -> Result<String> {
-        match self.provider {
+    pub fn send_request(&self, prompt: &str) -> Result<LlmResponse> {
+        let id = self.reserve(prompt.len())?;
+
+        let started = Instant::now();
+        let result = match self.provider {
             LlmProvider::Gemini => self.send_gemini_request(prompt),
+        };
+        let latency = started.elapsed();
+
+        if let Some(ref ledger) = self.ledger
+            && let Err(e) = ledger.record(
+                self.model(),
+                self.prompt_name.as_deref(),
+                &result,
+                latency,
+                id,
+            )
+        {
+            eprintln!("Warning: Failed to record usage: {e:#}");
+        }
+
+        let mut response = result?;
+        response.latency = latency;
+        Ok(response)
+    }
+
+    fn reserve(&self, input_len: usize) -> Result<Option<String>> {
+        let Some(ref ledger) = self.ledger else {
+            return Ok(None);
+        };
+        ledger.reserve(
+            self.model(),
+            self.prompt_name.as_deref(),
+            input_len,
+            SystemTime::now(),
+        )
+    }
+
+    pub fn with_ledger(mut self, ledger: Ledger) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    pub fn with_prompt_name(mut self, name: Option<&str>) -> Self {
+        self.prompt_name = name.map(str::to_string);
+        self
+    }
+
+    pub fn provider(&self) -> &'static str {
+        self.provider.as_str()
+    }
+
+    pub fn model(&self) -> &str {
+        match self.provider {
+            LlmProvider::Gemini => self
+                .config
+                .gemini
+                .as_ref()
+                .map_or("", |gemini| gemini.model.as_str()),
+        }
+    }
+
+    pub fn with_overrides(&self, overrides: &GeminiOverrides) -> Result<Self> {
+        let mut client = self.clone();
+        match client.provider {
+            LlmProvider::Gemini => client
+                .config
+                .gemini
+                .as_mut()
+                .ok_or_else(|| anyhow!("Gemini configuration not found"))?
+                .apply(overrides),
+        }
+        Ok(client)
+    }
+
+    fn scrub(&self, error: impl std::fmt::Display) -> anyhow::Error {
+        anyhow!("{}", scrub(&format!("{error:#}"), &self.api_key))
+    }
+
+    fn execute(&self, request: RequestBuilder) -> Result<(StatusCode, String)> {
+        let request = request
+            .header(API_KEY_HEADER, &self.api_key)
+            .build()
+            .map_err(|e| self.scrub(e))?;
+        self.logger.log(
+            log::DEBUG,
+            "request",
+            &scrub(
+                &format!("{} {}", request.method(), request.url()),
+                &self.api_key,
+            ),
+        );
+
+        let started = Instant::now();
+        let progress = transfer(&self.client, request);
+        let mut status = StatusCode::OK;
+        let mut body = Vec::new();
+        loop {
+            let event = match self.config.timeouts.read_timeout {
+                Some(ms) => progress.recv_timeout(millis(ms)).map_err(|e| match e {
+                    RecvTimeoutError::Timeout => Some(ms),
+                    RecvTimeoutError::Disconnected => None,
+                }),
+                None => progress.recv().map_err(|_| None),
+            };
+            match event {
+                Ok(Progress::Head(head_status, headers)) => {
+                    status = head_status;
+                    self.log_head(status, &headers, started.elapsed());
+                }
+                Ok(Progress::Chunk(chunk)) => body.extend_from_slice(&chunk),
+                Ok(Progress::Done) => break,
+                Ok(Progress::Failed(e)) => return Err(self.request_error(e, started.elapsed())),
+                Ok(Progress::ReadFailed(e)) => {
+                    return Err(error::classify(self.scrub(e), ErrorKind::Network));
+                }
+                Err(Some(ms)) => {
+                    return Err(error::classify(
+                        anyhow!("read_timeout of {ms} ms exceeded while waiting for the response"),
+                        ErrorKind::Network,
+                    ));
+                }
+                Err(None) => {
+                    return Err(error::classify(
+                        anyhow!("Request ended without a response"),
+                        ErrorKind::Network,
+                    ));
+                }
+            }
+        }
+        self.logger.timing("send", started.elapsed());
+        Ok((status, String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    fn log_head(&self, status: StatusCode, headers: &HeaderMap, elapsed: Duration) {
+        self.logger.timing("ttfb", elapsed);
+        if !self.logger.enabled(log::DEBUG) {
+            return;
+        }
+        self.logger.log(log::DEBUG, "response", status.as_str());
+        for (name, value) in headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            self.logger
+                .log(log::DEBUG, "response", &format!("{name}: {value}"));
+        }
+    }
+
+    fn request_error(&self, error: reqwest::Error, elapsed: Duration) -> anyhow::Error {
+        let error = match timeout_fired(&self.config.timeouts, &error, elapsed) {
+            Some((name, ms)) => self.scrub(format!("{name} of {ms} ms exceeded: {error:#}")),
+            None => self.scrub(error),
+        };
+        error::classify(error, ErrorKind::Network)
+    }
+
+    fn api_failure(&self, status: StatusCode, message: String) -> anyhow::Error {
+        let kind = match status.as_u16() {
+            401 | 403 => ErrorKind::Auth,
+            400 if message.contains("API key") => ErrorKind::Auth,
+            429 => ErrorKind::RateLimit,
+            500.. => ErrorKind::Network,
+            _ => ErrorKind::Other,
+        };
+        error::classify(self.scrub(message), kind)
+    }
+}
+
+enum Progress {
+    Head(StatusCode, HeaderMap),
+    Chunk(Vec<u8>),
+    Done,
+    Failed(reqwest::Error),
+    ReadFailed(io::Error),
+}
+
+// The request runs on its own thread so that the caller can give up once
+// read_timeout passes without any progress; total_timeout ends the thread.
+fn transfer(client: &Client, request: Request) -> Receiver<Progress> {
+    let (sender, receiver) = mpsc::channel();
+    let client = client.clone();
+    thread::spawn(move || {
+        let mut res = match client.execute(request) {
+            Ok(res) => res,
+            Err(e) => {
+                let _ = sender.send(Progress::Failed(e));
+                return;
+            }
+        };
+        if sender
+            .send(Progress::Head(res.status(), res.headers().clone()))
+            .is_err()
+        {
+            return;
+        }
+        let mut buf = vec![0; 16 * 1024];
+        loop {
+            let progress = match res.read(&mut buf) {
+                Ok(0) => Progress::Done,
+                Ok(n) => Progress::Chunk(buf[..n].to_vec()),
+                Err(e) => match e.into_inner().map(|e| e.downcast::<reqwest::Error>()) {
+                    Some(Ok(e)) => Progress::Failed(*e),
+                    Some(Err(e)) => Progress::ReadFailed(io::Error::other(e)),
+                    None => Progress::ReadFailed(io::Error::other("Failed to read the response")),
+                },
+            };
+            let last = !matches!(progress, Progress::Chunk(_));
+            if sender.send(progress).is_err() || last {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+fn http_client(config: &Config) -> Result<Client> {
+    let http = &config.http;
+    let timeouts = &config.timeouts;
+    let mut builder = Client::builder()
+        .connect_timeout(timeouts.connect_timeout.map(millis))
+        .timeout(timeouts.total_timeout.map(millis));
+
+    let proxy = match (&http.proxy, &http.no_proxy) {
+        (Some(proxy), _) => Some(proxy.clone()),
+        (None, Some(_)) => PROXY_VARS.iter().find_map(|name| env::var(name).ok()),
+        (None, None) => None,
+    };
+    if let Some(ref proxy) = proxy {
+        let no_proxy = match http.no_proxy {
+            Some(ref hosts) => NoProxy::from_string(hosts),
+            None => NoProxy::from_env(),
+        };
+        let proxy = Proxy::all(proxy)
+            .map_err(|e| anyhow!("Invalid proxy '{}': {}", redact_userinfo(proxy), e))?
+            .no_proxy(no_proxy);
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(ref path) = http.ca_bundle {
+        let pem = fs::read(path)
+            .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid CA bundle {}", path.display()))?;
+        anyhow::ensure!(
+            !certificates.is_empty(),
+            "No certificates found in CA bundle {}",
+            path.display()
+        );
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if let Some(ref path) = http.client_cert {
+        let mut pem = fs::read(path)
+            .with_context(|| format!("Failed to read client certificate {}", path.display()))?;
+        if let Some(ref key) = http.client_key {
+            pem.push(b'\n');
+            pem.extend(
+                fs::read(key)
+                    .with_context(|| format!("Failed to read client key {}", key.display()))?,
+            );
+        }
+        let identity = Identity::from_pem(&pem)
+            .with_context(|| format!("Invalid client certificate {}", path.display()))?;
+        builder = builder.identity(identity);
+    }
+
+    Ok(builder.build()?)
+}
+
+fn timeout_fired(
+    timeouts: &Timeouts,
+    error: &reqwest::Error,
+    elapsed: Duration,
+) -> Option<(&'static str, u32)> {
+    if !error.is_timeout() {
+        return None;
+    }
+    let total = timeouts
+        .total_timeout
+        .filter(|&ms| elapsed >= millis(ms))
+        .map(|ms| ("total_timeout", ms));
+    let connect = timeouts
+        .connect_timeout
+        .filter(|_| error.is_connect())
+        .map(|ms| ("connect_timeout", ms));
+    total.or(connect)
+}
+
+fn millis(ms: u32) -> Duration {
+    Duration::from_millis(ms.into())
+}
+
+pub fn redact_userinfo(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme => {
+            format!("{}[REDACTED]{}", &url[..scheme + 3], &url[at..])
         }
+        _ => url.to_string(),
     }
 }
 
@@ -132,15 +517,59 @@ struct RequestBody {
     generation_config: Option<ApiGenerationConfig>,
 }
 
+impl RequestBody {
+    fn new(prompt: &str, gemini_config: &GeminiConfig) -> Self {
+        RequestBody {
+            contents: vec![Content {
+                parts: vec![Part {
+                    text: prompt.to_string(),
+                }],
+            }],
+            generation_config: Some(ApiGenerationConfig::from(gemini_config)),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct ResponseBody {
     candidates: Option<Vec<Candidate>>,
     error: Option<ApiError>,
+    #[serde(default, rename = "promptFeedback")]
+    prompt_feedback: Option<Value>,
+    #[serde(default, rename = "responseId")]
+    response_id: Option<String>,
+    #[serde(default, rename = "usageMetadata")]
+    usage_metadata: Option<ApiUsageMetadata>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiUsageMetadata {
+    #[serde(default, rename = "promptTokenCount")]
+    prompt_token_count: u32,
+    #[serde(default, rename = "candidatesTokenCount")]
+    candidates_token_count: u32,
+    #[serde(default, rename = "thoughtsTokenCount")]
+    thoughts_token_count: u32,
+    #[serde(default, rename = "totalTokenCount")]
+    total_token_count: u32,
+}
+
+impl From<&ApiUsageMetadata> for Usage {
+    fn from(um: &ApiUsageMetadata) -> Self {
+        Usage {
+            prompt_tokens: um.prompt_token_count,
+            output_tokens: um.candidates_token_count,
+            thinking_tokens: um.thoughts_token_count,
+            total_tokens: um.total_token_count,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct Candidate {
     content: Option<Content>,
+    #[serde(default, rename = "finishReason")]
+    finish_reason: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -150,82 +579,168 @@ struct ApiError {
 }
 
 impl LlmClient {
-    fn send_gemini_request(&self, prompt: &str) -> Result<String> {
+    fn send_gemini_request(&self, prompt: &str) -> Result<LlmResponse> {
         let gemini_config: &GeminiConfig = self
             .config
             .gemini
             .as_ref()
             .ok_or_else(|| anyhow!("Gemini configuration not found"))?;
 
-        let model = &gemini_config.model;
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent",
-            model
-        );
-
-        let api_generation_config = self.config.gemini.as_ref().map(ApiGenerationConfig::from);
+        let (url, request_body) = self.gemini_request(prompt, gemini_config);
 
-        let request_body = RequestBody {
-            contents: vec![Content {
-                parts: vec![Part {
-                    text: prompt.to_string(),
-                }],
-            }],
-            generation_config: api_generation_config,
-        };
-
-        if self.verbose {
-            eprintln!("--- LLM API Request ---");
-            eprintln!("URL: {url}");
-            eprintln!("Body: {}", serde_json::to_string_pretty(&request_body)?);
-            eprintln!("-----------------------");
+        if self.logger.enabled(log::TRACE) {
+            self.logger.log(
+                log::TRACE,
+                "body",
+                &scrub(&serde_json::to_string_pretty(&request_body)?, &self.api_key),
+            );
         }
 
-        let res = self
-            .client
-            .post(&url)
-            .query(&[("key", &self.api_key)])
-            .json(&request_body)
-            .send()?;
-
-        let status = res.status();
-        let body_text = res.text()?;
+        let (status, body_text) = self.execute(self.client.post(&url).json(&request_body))?;
 
+        let started = Instant::now();
         let body: ResponseBody = serde_json::from_str(&body_text).map_err(|e| {
-            anyhow!(
-                "Failed to deserialize Gemini API response: {} - Body: {}",
-                e,
-                body_text
+            self.api_failure(
+                status,
+                format!("Failed to deserialize Gemini API response: {e} - Body: {body_text}"),
             )
         })?;
 
         if !status.is_success() {
             if let Some(api_error) = body.error {
-                return Err(anyhow!(
-                    "LLM API error {}: {}",
-                    api_error.code,
-                    api_error.message
+                return Err(self.api_failure(
+                    status,
+                    format!("LLM API error {}: {}", api_error.code, api_error.message),
                 ));
             } else {
-                return Err(anyhow!(
-                    "LLM request failed with status {}: {:?}",
+                return Err(self.api_failure(
                     status,
-                    body
+                    format!("LLM request failed with status {status}: {body:?}"),
                 ));
             }
         }
 
-        extract_text(body)
+        let response =
+            extract_response(body).map_err(|e| error::classify(self.scrub(&e), error::kind(&e)))?;
+        self.logger.timing("parse", started.elapsed());
+        Ok(response)
     }
+
+    fn gemini_request(&self, prompt: &str, gemini_config: &GeminiConfig) -> (String, RequestBody) {
+        let url = format!(
+            "{}/v1beta/models/{}:generateContent",
+            self.base_url, gemini_config.model
+        );
+        (url, RequestBody::new(prompt, gemini_config))
+    }
+
+    pub fn dry_run(&self, prompt: &str, format: DryRun) -> Result<String> {
+        let (url, body) = match self.provider {
+            LlmProvider::Gemini => {
+                let gemini_config = self
+                    .config
+                    .gemini
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Gemini configuration not found"))?;
+                self.gemini_request(prompt, gemini_config)
+            }
+        };
+        let request = self
+            .client
+            .post(&url)
+            .header(API_KEY_HEADER, &self.api_key)
+            .json(&body)
+            .build()?;
+        let headers: Vec<(&str, Option<String>)> = request
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = (name.as_str() != API_KEY_HEADER)
+                    .then(|| String::from_utf8_lossy(value.as_bytes()).into_owned());
+                (name.as_str(), value)
+            })
+            .collect();
+        let body = serde_json::to_string_pretty(&body)?;
+
+        Ok(match format {
+            DryRun::Request => {
+                let mut lines = vec![format!("{} {}", request.method(), request.url())];
+                for (name, value) in headers {
+                    lines.push(format!(
+                        "{name}: {}",
+                        value.as_deref().unwrap_or("[REDACTED]")
+                    ));
+                }
+                lines.push(String::new());
+                lines.push(body);
+                lines.join("\n")
+            }
+            DryRun::Curl => {
+                let mut lines = vec![format!(
+                    "curl -X {} {}",
+                    request.method(),
+                    shell_quote(request.url().as_str())
+                )];
+                for (name, value) in headers {
+                    lines.push(match value {
+                        Some(value) => format!("-H {}", shell_quote(&format!("{name}: {value}"))),
+                        None => format!("-H \"{name}: ${KEY_VAR}\""),
+                    });
+                }
+                lines.push(format!("--data-raw {}", shell_quote(&body)));
+                lines.join(" \\\n  ")
+            }
+        })
+    }
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
 }
 
-fn extract_text(body: ResponseBody) -> Result<String> {
+fn scrub(text: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        return text.to_string();
+    }
+    text.replace(secret, "[REDACTED]")
+}
+
+fn extract_response(body: ResponseBody) -> Result<LlmResponse> {
     if let Some(ref candidates) = body.candidates
         && let Some(candidate) = candidates.iter().next()
         && let Some(ref content) = candidate.content
         && let Some(part) = content.parts.first()
     {
-        return Ok(part.text.clone());
+        return Ok(LlmResponse {
+            text: part.text.clone(),
+            finish_reason: candidate.finish_reason.clone(),
+            usage: body.usage_metadata.as_ref().map(Usage::from),
+            request_id: body.response_id.clone(),
+            ..Default::default()
+        });
+    }
+
+    let block_reason = body
+        .prompt_feedback
+        .as_ref()
+        .and_then(|feedback| feedback.get("blockReason"))
+        .and_then(|reason| reason.as_str());
+    if let Some(reason) = block_reason {
+        return Err(error::classify(
+            anyhow!("The prompt was blocked by the provider: {}", reason),
+            ErrorKind::Safety,
+        ));
+    }
+    let finish_reason = body
+        .candidates
+        .as_ref()
+        .and_then(|candidates| candidates.first())
+        .and_then(|candidate| candidate.finish_reason.as_deref());
+    if let Some(reason) = finish_reason.filter(|r| BLOCK_REASONS.contains(r)) {
+        return Err(error::classify(
+            anyhow!("The response was blocked by the provider: {}", reason),
+            ErrorKind::Safety,
+        ));
     }
 
     Err(anyhow!(
@@ -237,18 +752,23 @@ fn extract_text(body: ResponseBody) -> Result<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::HttpConfig;
+    use crate::mock::MockServer;
+    use crate::usage::UsageConfig;
     use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
 
     #[test]
     fn test_new_client_unsupported_provider() {
         let config = Config {
             version: 1,
             provider: "unknown".to_string(),
-            timeout: 1000,
             gemini: None,
             prompt: HashMap::new(),
+            ..Default::default()
         };
-        let result = LlmClient::new(&config, false);
+        let result = LlmClient::new(&config, &Logger::default());
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
@@ -261,11 +781,11 @@ mod tests {
         let config = Config {
             version: 1,
             provider: "gemini".to_string(),
-            timeout: 1000,
             gemini: None,
             prompt: HashMap::new(),
+            ..Default::default()
         };
-        let result = LlmClient::new(&config, false);
+        let result = LlmClient::new(&config, &Logger::default());
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
@@ -277,6 +797,9 @@ mod tests {
     fn test_gemini_thinking_config_logic() {
         let mut gc = GeminiConfig {
             key: "key".to_string(),
+            key_file: None,
+            key_command: None,
+            base_url: None,
             model: "model".to_string(),
             temperature: Some(1.0),
             top_p: None,
@@ -299,7 +822,7 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_text_success() {
+    fn test_extract_response_success() {
         let body = ResponseBody {
             candidates: Some(vec![Candidate {
                 content: Some(Content {
@@ -307,20 +830,29 @@ mod tests {
                         text: "hello world".to_string(),
                     }],
                 }),
+                finish_reason: Some("STOP".to_string()),
             }]),
             error: None,
+            prompt_feedback: None,
+            response_id: None,
+            usage_metadata: None,
         };
-        let res = extract_text(body).unwrap();
-        assert_eq!(res, "hello world");
+        let res = extract_response(body).unwrap();
+        assert_eq!(res.text, "hello world");
+        assert_eq!(res.finish_reason, Some("STOP".to_string()));
+        assert!(!res.is_truncated());
     }
 
     #[test]
-    fn test_extract_text_no_candidates() {
+    fn test_extract_response_no_candidates() {
         let body = ResponseBody {
             candidates: None,
             error: None,
+            prompt_feedback: None,
+            response_id: None,
+            usage_metadata: None,
         };
-        let res = extract_text(body);
+        let res = extract_response(body);
         assert!(res.is_err());
         assert!(
             res.unwrap_err()
@@ -361,8 +893,378 @@ mod tests {
         }"#;
         let body: ResponseBody = serde_json::from_str(json).unwrap();
         assert!(body.candidates.is_some());
-        let text = extract_text(body).unwrap();
-        assert_eq!(text, "result");
+        let response = extract_response(body).unwrap();
+        assert_eq!(response.text, "result");
+        assert!(response.finish_reason.is_none());
+    }
+
+    #[test]
+    fn test_response_body_deserialization_truncated() {
+        let json = r#"{
+            "candidates": [
+                {
+                    "content": { "parts": [{ "text": "partial" }] },
+                    "finishReason": "MAX_TOKENS"
+                }
+            ]
+        }"#;
+        let body: ResponseBody = serde_json::from_str(json).unwrap();
+        let response = extract_response(body).unwrap();
+        assert!(response.is_truncated());
+    }
+
+    #[test]
+    fn test_response_body_deserialization_usage() {
+        let json = r#"{
+            "candidates": [{ "content": { "parts": [{ "text": "ok" }] } }],
+            "usageMetadata": {
+                "promptTokenCount": 10,
+                "candidatesTokenCount": 5,
+                "thoughtsTokenCount": 2,
+                "totalTokenCount": 17
+            }
+        }"#;
+        let body: ResponseBody = serde_json::from_str(json).unwrap();
+        let response = extract_response(body).unwrap();
+        assert_eq!(
+            response.usage,
+            Some(Usage {
+                prompt_tokens: 10,
+                output_tokens: 5,
+                thinking_tokens: 2,
+                total_tokens: 17,
+            })
+        );
+    }
+
+    fn mock_client(base_url: &str, key: &str) -> LlmClient {
+        let config = Config {
+            version: 1,
+            provider: "gemini".to_string(),
+            timeouts: crate::config::Timeouts {
+                total_timeout: Some(5000),
+                ..Default::default()
+            },
+            gemini: Some(GeminiConfig {
+                key: key.to_string(),
+                key_file: None,
+                key_command: None,
+                base_url: Some(base_url.to_string()),
+                model: "gemini-test".to_string(),
+                temperature: None,
+                top_p: None,
+                top_k: None,
+                max_output_tokens: None,
+                thinking_budget: None,
+                thinking_level: None,
+            }),
+            prompt: HashMap::new(),
+            ..Default::default()
+        };
+        LlmClient::without_key(&config, &Logger::default())
+            .and_then(|client| client.with_key(key.to_string()))
+            .unwrap()
+    }
+
+    const KEY: &str = "AIzaSecretTestKey123";
+
+    #[test]
+    fn test_send_request_uses_key_header() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "POST",
+            "/v1beta/models/gemini-test:generateContent",
+            200,
+            r#"{"candidates":[{"content":{"parts":[{"text":"hi"}]}}],"responseId":"abc123"}"#
+                .to_string(),
+        )]);
+        let response = mock_client(server.url(), KEY).send_request("hello")?;
+        assert_eq!(response.text, "hi");
+        assert_eq!(response.request_id.as_deref(), Some("abc123"));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header(API_KEY_HEADER), Some(KEY));
+        assert!(!requests[0].path.contains(KEY));
+        assert!(!requests[0].path.contains("key="));
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_request_records_usage_and_enforces_budget() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "POST",
+            "/v1beta/models/gemini-test:generateContent",
+            200,
+            r#"{"candidates":[{"content":{"parts":[{"text":"hi"}]}}],"usageMetadata":{"promptTokenCount":7,"candidatesTokenCount":3,"totalTokenCount":10}}"#
+                .to_string(),
+        )]);
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("usage.jsonl");
+        let budget = UsageConfig {
+            daily_tokens: Some(10),
+            ..Default::default()
+        };
+        let client = mock_client(server.url(), KEY)
+            .with_prompt_name(Some("review"))
+            .with_ledger(Ledger::new(path.clone(), budget));
+
+        client.send_request("hello")?;
+        let ledger = fs::read_to_string(&path)?;
+        assert!(ledger.contains(r#""prompt":"review","model":"gemini-test""#));
+        assert!(ledger.contains(r#""total_tokens":10"#));
+
+        let err = client.send_request("hello").unwrap_err();
+        assert_eq!(error::kind(&err), ErrorKind::Budget);
+        assert_eq!(server.requests().len(), 1);
+        let ledger = fs::read_to_string(&path)?;
+        assert_eq!(ledger.lines().count(), 2);
+        assert!(
+            ledger
+                .lines()
+                .next()
+                .unwrap()
+                .contains(r#""status":"pending""#)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_requests_count_towards_budget() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "POST",
+            "/v1beta/models/gemini-test:generateContent",
+            200,
+            r#"{"candidates":[{"content":{"parts":[{"text":"hi"}]}}],"usageMetadata":{"promptTokenCount":7,"candidatesTokenCount":3,"totalTokenCount":10}}"#
+                .to_string(),
+        )]);
+        let dir = tempfile::tempdir()?;
+        let budget = UsageConfig {
+            daily_tokens: Some(10),
+            ..Default::default()
+        };
+        let client = mock_client(server.url(), KEY)
+            .with_ledger(Ledger::new(dir.path().join("usage.jsonl"), budget));
+
+        let results: Vec<Result<LlmResponse>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| client.send_request(&"x".repeat(40))))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(
+            results
+                .iter()
+                .filter_map(|r| r.as_ref().err())
+                .all(|e| error::kind(e) == ErrorKind::Budget)
+        );
+        assert_eq!(server.requests().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_redacts_key() -> Result<()> {
+        let client = mock_client("http://127.0.0.1:9", KEY);
+
+        let request = client.dry_run("it's", DryRun::Request)?;
+        assert!(
+            request
+                .starts_with("POST http://127.0.0.1:9/v1beta/models/gemini-test:generateContent\n")
+        );
+        assert!(request.contains("content-type: application/json\n"));
+        assert!(request.contains("x-goog-api-key: [REDACTED]\n"));
+        assert!(request.contains("\"text\": \"it's\""));
+        assert!(!request.contains(KEY));
+
+        let curl = client.dry_run("it's", DryRun::Curl)?;
+        assert!(curl.starts_with(
+            "curl -X POST 'http://127.0.0.1:9/v1beta/models/gemini-test:generateContent' \\\n"
+        ));
+        assert!(curl.contains("-H \"x-goog-api-key: $GEMINI_API_KEY\""));
+        assert!(curl.contains("\"text\": \"it'\\''s\""));
+        assert!(!curl.contains(KEY));
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_url_trailing_slash() {
+        let client = mock_client("http://127.0.0.1:9/gemini/", KEY);
+        assert_eq!(client.base_url, "http://127.0.0.1:9/gemini");
+    }
+
+    #[test]
+    fn test_send_request_through_proxy() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "POST",
+            "http://gemini.invalid/v1beta/models/gemini-test:generateContent",
+            200,
+            r#"{"candidates":[{"content":{"parts":[{"text":"via proxy"}]}}]}"#.to_string(),
+        )]);
+        let mut client = mock_client("http://gemini.invalid", KEY);
+        client.config.http.proxy = Some(server.url().to_string());
+        client.client = http_client(&client.config)?;
+
+        assert_eq!(client.send_request("hello")?.text, "via proxy");
+        assert_eq!(server.requests().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_client_errors() {
+        let mut config = Config::default();
+        config.http.proxy = Some("http://user:secret@[::1".to_string());
+        let err = http_client(&config).unwrap_err().to_string();
+        assert!(err.contains("http://[REDACTED]@[::1"), "{err}");
+        assert!(!err.contains("secret"), "{err}");
+
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("ca.pem");
+        fs::write(&bundle, "not a certificate").unwrap();
+        let config = Config {
+            http: HttpConfig {
+                ca_bundle: Some(bundle),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let err = http_client(&config).unwrap_err().to_string();
+        assert!(err.contains("ca.pem"), "{err}");
+    }
+
+    fn stalled_client(timeouts: Timeouts) -> (LlmClient, TcpListener) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut client = mock_client(&url, KEY);
+        client.config.timeouts = timeouts;
+        client.client = http_client(&client.config).unwrap();
+        (client, listener)
+    }
+
+    #[test]
+    fn test_errors_name_the_timeout() {
+        let (client, _listener) = stalled_client(Timeouts {
+            read_timeout: Some(100),
+            total_timeout: Some(5000),
+            ..Default::default()
+        });
+        let err = client.send_request("hello").unwrap_err().to_string();
+        assert!(err.starts_with("read_timeout of 100 ms exceeded"), "{err}");
+
+        let (client, _listener) = stalled_client(Timeouts {
+            total_timeout: Some(100),
+            ..Default::default()
+        });
+        let err = client.send_request("hello").unwrap_err().to_string();
+        assert!(err.starts_with("total_timeout of 100 ms exceeded"), "{err}");
+    }
+
+    #[test]
+    fn test_read_timeout_applies_to_the_body() {
+        let server = MockServer::start(vec![(
+            "POST",
+            "/v1beta/models/gemini-test:generateContent",
+            200,
+            r#"{"candidates":[{"content":{"parts":[{"text":"hi"}]}}]}"#.to_string(),
+        )]);
+        let mut client = mock_client(server.url(), KEY);
+        client.config.timeouts.read_timeout = Some(1000);
+        client.client = http_client(&client.config).unwrap();
+        assert_eq!(client.send_request("hello").unwrap().text, "hi");
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while !String::from_utf8_lossy(&request).contains("hello") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\n{");
+            std::thread::sleep(Duration::from_secs(2));
+        });
+        let mut client = mock_client(&url, KEY);
+        client.config.timeouts.read_timeout = Some(100);
+        client.client = http_client(&client.config).unwrap();
+        let err = client.send_request("hello").unwrap_err().to_string();
+        assert!(err.starts_with("read_timeout of 100 ms exceeded"), "{err}");
+    }
+
+    #[test]
+    fn test_errors_do_not_contain_key() {
+        let path = "/v1beta/models/gemini-test:generateContent";
+        let cases = [
+            (
+                400,
+                format!(r#"{{"error":{{"code":400,"message":"API key {KEY} not valid"}}}}"#),
+            ),
+            (502, format!("<html>bad gateway for {KEY}</html>")),
+            (500, format!(r#"{{"unexpected":"{KEY}"}}"#)),
+            (200, format!(r#"{{"promptFeedback":"{KEY}"}}"#)),
+        ];
+        for (status, body) in cases {
+            let routes = vec![("POST", path, status, body)];
+            let server = MockServer::start(routes);
+            let err = mock_client(server.url(), KEY)
+                .send_request("hello")
+                .unwrap_err();
+            for text in [format!("{err}"), format!("{err:#}"), format!("{err:?}")] {
+                assert!(!text.contains(KEY), "key leaked: {text}");
+            }
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let err = mock_client(&url, KEY).send_request("hello").unwrap_err();
+        assert!(!format!("{err:?}").contains(KEY));
+    }
+
+    #[test]
+    fn test_errors_are_classified() {
+        let path = "/v1beta/models/gemini-test:generateContent";
+        let error =
+            |code, message| format!(r#"{{"error":{{"code":{code},"message":"{message}"}}}}"#);
+        let cases = [
+            (400, error(400, "API key not valid"), ErrorKind::Auth),
+            (403, error(403, "Permission denied"), ErrorKind::Auth),
+            (429, error(429, "Resource exhausted"), ErrorKind::RateLimit),
+            (
+                503,
+                "<html>unavailable</html>".to_string(),
+                ErrorKind::Network,
+            ),
+            (400, error(400, "Invalid JSON payload"), ErrorKind::Other),
+            (
+                200,
+                r#"{"promptFeedback":{"blockReason":"PROHIBITED_CONTENT"}}"#.to_string(),
+                ErrorKind::Safety,
+            ),
+            (
+                200,
+                r#"{"candidates":[{"finishReason":"SAFETY"}]}"#.to_string(),
+                ErrorKind::Safety,
+            ),
+        ];
+        for (status, body, kind) in cases {
+            let server = MockServer::start(vec![("POST", path, status, body)]);
+            let err = mock_client(server.url(), KEY)
+                .send_request("hello")
+                .unwrap_err();
+            assert_eq!(error::kind(&err), kind, "{err:#}");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let err = mock_client(&url, KEY).send_request("hello").unwrap_err();
+        assert_eq!(error::kind(&err), ErrorKind::Network);
+    }
+
+    #[test]
+    fn test_scrub() {
+        assert_eq!(scrub("a key b key", "key"), "a [REDACTED] b [REDACTED]");
+        assert_eq!(scrub("text", ""), "text");
     }
 
     #[test]
@@ -370,9 +1272,11 @@ mod tests {
         let config = Config {
             version: 1,
             provider: "gemini".to_string(),
-            timeout: 1000,
             gemini: Some(GeminiConfig {
                 key: "YOUR_GEMINI_API_KEY".to_string(),
+                key_file: None,
+                key_command: None,
+                base_url: None,
                 model: "model".to_string(),
                 temperature: None,
                 top_p: None,
@@ -382,8 +1286,10 @@ mod tests {
                 thinking_level: None,
             }),
             prompt: HashMap::new(),
+            ..Default::default()
         };
-        let result = LlmClient::new(&config, false);
+        let result = LlmClient::without_key(&config, &Logger::default())
+            .and_then(|client| client.with_key(KEY_PLACEHOLDER.to_string()));
         assert!(result.is_err());
         assert!(
             result
@@ -0,0 +1,667 @@
+use anyhow::{Result, anyhow};
+use reqwest::blocking::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Instant;
+
+use super::{ApiError, LlmClient, LlmProvider, LlmResponse, RequestBody, ResponseBody, Usage};
+use crate::config::{GeminiConfig, GeminiOverrides};
+use crate::usage::Ledger;
+
+pub struct BatchEntry {
+    pub key: String,
+    pub prompt: String,
+    pub overrides: GeminiOverrides,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchJob {
+    pub name: String,
+    pub state: String,
+    pub request_count: u64,
+    pub succeeded_count: u64,
+    pub failed_count: u64,
+    pub pending_count: u64,
+}
+
+impl BatchJob {
+    pub fn is_succeeded(&self) -> bool {
+        self.state.ends_with("_SUCCEEDED")
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchResult {
+    pub key: String,
+    pub response: Result<LlmResponse>,
+}
+
+#[derive(Serialize)]
+struct ApiBatchRequest {
+    batch: ApiBatch,
+}
+
+#[derive(Serialize)]
+struct ApiBatch {
+    display_name: String,
+    input_config: ApiInputConfig,
+}
+
+#[derive(Serialize)]
+struct ApiInputConfig {
+    requests: ApiInlinedRequests,
+}
+
+#[derive(Serialize)]
+struct ApiInlinedRequests {
+    requests: Vec<ApiInlinedRequest>,
+}
+
+#[derive(Serialize)]
+struct ApiInlinedRequest {
+    request: RequestBody,
+    metadata: ApiMetadata,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ApiMetadata {
+    key: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiOperation {
+    name: String,
+    #[serde(default)]
+    metadata: Option<ApiBatchMetadata>,
+    #[serde(default)]
+    response: Option<ApiBatchOutput>,
+    #[serde(default)]
+    error: Option<ApiError>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiBatchMetadata {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+    #[serde(default, rename = "batchStats")]
+    batch_stats: Option<ApiBatchStats>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ApiBatchStats {
+    #[serde(default, rename = "requestCount")]
+    request_count: Option<Value>,
+    #[serde(default, rename = "successfulRequestCount")]
+    successful_request_count: Option<Value>,
+    #[serde(default, rename = "failedRequestCount")]
+    failed_request_count: Option<Value>,
+    #[serde(default, rename = "pendingRequestCount")]
+    pending_request_count: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiBatchOutput {
+    #[serde(default, rename = "inlinedResponses")]
+    inlined_responses: Option<ApiInlinedResponses>,
+    #[serde(default, rename = "responsesFile")]
+    responses_file: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiInlinedResponses {
+    #[serde(default, rename = "inlinedResponses")]
+    inlined_responses: Vec<ApiInlinedResponse>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiInlinedResponse {
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    metadata: Option<ApiMetadata>,
+    #[serde(default)]
+    response: Option<ResponseBody>,
+    #[serde(default)]
+    error: Option<ApiError>,
+}
+
+impl LlmClient {
+    pub fn submit_batch(&self, display_name: &str, entries: &[BatchEntry]) -> Result<BatchJob> {
+        let gemini_config = self.batch_config()?;
+        anyhow::ensure!(!entries.is_empty(), "Batch has no requests");
+
+        let mut requests = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let mut entry_config = gemini_config.clone();
+            entry_config.apply(&entry.overrides);
+            anyhow::ensure!(
+                entry_config.model == gemini_config.model,
+                "Batch request '{}' overrides the model, but all requests in a batch must use {}",
+                entry.key,
+                gemini_config.model
+            );
+            requests.push(ApiInlinedRequest {
+                request: RequestBody::new(&entry.prompt, &entry_config),
+                metadata: ApiMetadata {
+                    key: entry.key.clone(),
+                },
+            });
+        }
+
+        let request_body = ApiBatchRequest {
+            batch: ApiBatch {
+                display_name: display_name.to_string(),
+                input_config: ApiInputConfig {
+                    requests: ApiInlinedRequests { requests },
+                },
+            },
+        };
+
+        let url = format!(
+            "{}/v1beta/models/{}:batchGenerateContent",
+            self.base_url, gemini_config.model
+        );
+        let input_len = entries.iter().map(|entry| entry.prompt.len()).sum();
+        let id = self.reserve(input_len)?;
+        let started = Instant::now();
+        let result = self
+            .send_batch_call(self.client.post(&url).json(&request_body))
+            .and_then(|body_text| parse_operation(&body_text))
+            .map(|operation| batch_job(&operation));
+        if let Some(ref ledger) = self.ledger {
+            let name = result
+                .as_ref()
+                .map_or(display_name, |job| job.name.as_str());
+            let usage = Usage::default();
+            if let Err(e) = ledger.record_batch(
+                &gemini_config.model,
+                name,
+                &result,
+                usage,
+                started.elapsed(),
+                id,
+            ) {
+                eprintln!("Warning: Failed to record usage: {e:#}");
+            }
+        }
+        result
+    }
+
+    pub fn batch_status(&self, name: &str) -> Result<BatchJob> {
+        self.batch_config()?;
+        let operation = self.get_operation(name)?;
+        Ok(batch_job(&operation))
+    }
+
+    pub fn batch_results(&self, name: &str) -> Result<Vec<BatchResult>> {
+        self.batch_config()?;
+        let operation = self.get_operation(name)?;
+        let job = batch_job(&operation);
+        anyhow::ensure!(
+            job.is_succeeded(),
+            "Batch {} has not succeeded (state: {})",
+            job.name,
+            job.state
+        );
+
+        let output = operation
+            .response
+            .ok_or_else(|| anyhow!("Batch {} has no output", job.name))?;
+
+        let responses = match (output.inlined_responses, output.responses_file) {
+            (Some(inlined), _) => inlined.inlined_responses,
+            (None, Some(file)) => self.download_responses(&file)?,
+            (None, None) => return Err(anyhow!("Batch {} has no output", job.name)),
+        };
+
+        let results: Vec<BatchResult> = responses
+            .into_iter()
+            .enumerate()
+            .map(|(index, response)| batch_result(index, response))
+            .collect();
+        if let Some(ref ledger) = self.ledger {
+            let model = operation
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.model.as_deref())
+                .map_or(self.model(), |model| model.trim_start_matches("models/"));
+            if let Err(e) = record_results(ledger, model, &job.name, &results) {
+                eprintln!("Warning: Failed to record usage: {e:#}");
+            }
+        }
+        Ok(results)
+    }
+
+    pub fn cancel_batch(&self, name: &str) -> Result<()> {
+        self.batch_config()?;
+        let url = format!("{}/v1beta/{}:cancel", self.base_url, batch_path(name));
+        self.send_batch_call(self.client.post(&url).json(&serde_json::json!({})))?;
+        Ok(())
+    }
+
+    fn batch_config(&self) -> Result<&GeminiConfig> {
+        match self.provider {
+            LlmProvider::Gemini => self
+                .config
+                .gemini
+                .as_ref()
+                .ok_or_else(|| anyhow!("Gemini configuration not found")),
+        }
+    }
+
+    fn get_operation(&self, name: &str) -> Result<ApiOperation> {
+        let url = format!("{}/v1beta/{}", self.base_url, batch_path(name));
+        let body_text = self.send_batch_call(self.client.get(&url))?;
+        parse_operation(&body_text)
+    }
+
+    fn download_responses(&self, file: &str) -> Result<Vec<ApiInlinedResponse>> {
+        let url = format!("{}/download/v1beta/{}:download", self.base_url, file);
+        let body_text = self.send_batch_call(self.client.get(&url).query(&[("alt", "media")]))?;
+        body_text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| anyhow!("Failed to parse batch result line: {}", e))
+            })
+            .collect()
+    }
+
+    fn send_batch_call(&self, request: RequestBuilder) -> Result<String> {
+        let (status, body_text) = self.execute(request)?;
+
+        if !status.is_success() {
+            let api_error = serde_json::from_str::<ResponseBody>(&body_text)
+                .ok()
+                .and_then(|body| body.error);
+            if let Some(api_error) = api_error {
+                return Err(self.api_failure(
+                    status,
+                    format!("LLM API error {}: {}", api_error.code, api_error.message),
+                ));
+            }
+            return Err(self.api_failure(
+                status,
+                format!("LLM batch request failed with status {status}"),
+            ));
+        }
+
+        Ok(body_text)
+    }
+}
+
+fn record_results(ledger: &Ledger, model: &str, name: &str, results: &[BatchResult]) -> Result<()> {
+    let mut usage = Usage::default();
+    for result in results {
+        if let Ok(LlmResponse {
+            usage: Some(ref item),
+            ..
+        }) = result.response
+        {
+            usage.prompt_tokens += item.prompt_tokens;
+            usage.output_tokens += item.output_tokens;
+            usage.thinking_tokens += item.thinking_tokens;
+            usage.total_tokens += item.total_tokens;
+        }
+    }
+    ledger.record_batch_usage(model, name, usage)
+}
+
+fn parse_operation(body_text: &str) -> Result<ApiOperation> {
+    let operation: ApiOperation = serde_json::from_str(body_text)
+        .map_err(|e| anyhow!("Failed to deserialize Gemini batch response: {}", e))?;
+    if let Some(ref api_error) = operation.error {
+        return Err(anyhow!(
+            "LLM API error {}: {}",
+            api_error.code,
+            api_error.message
+        ));
+    }
+    Ok(operation)
+}
+
+fn batch_path(name: &str) -> String {
+    if name.starts_with("batches/") {
+        return name.to_string();
+    }
+    format!("batches/{name}")
+}
+
+fn batch_job(operation: &ApiOperation) -> BatchJob {
+    let metadata = operation.metadata.as_ref();
+    let default_stats = ApiBatchStats::default();
+    let stats = metadata
+        .and_then(|m| m.batch_stats.as_ref())
+        .unwrap_or(&default_stats);
+    BatchJob {
+        name: operation.name.clone(),
+        state: metadata
+            .and_then(|m| m.state.clone())
+            .unwrap_or_else(|| "UNKNOWN".to_string()),
+        request_count: count(&stats.request_count),
+        succeeded_count: count(&stats.successful_request_count),
+        failed_count: count(&stats.failed_request_count),
+        pending_count: count(&stats.pending_request_count),
+    }
+}
+
+fn count(value: &Option<Value>) -> u64 {
+    match value {
+        Some(Value::Number(n)) => n.as_u64().unwrap_or(0),
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn batch_result(index: usize, response: ApiInlinedResponse) -> BatchResult {
+    let key = response
+        .metadata
+        .map(|m| m.key)
+        .or(response.key)
+        .unwrap_or_else(|| index.to_string());
+    let response = match (response.error, response.response) {
+        (Some(api_error), _) => Err(anyhow!(
+            "LLM API error {}: {}",
+            api_error.code,
+            api_error.message
+        )),
+        (None, Some(body)) => super::extract_response(body),
+        (None, None) => Err(anyhow!("Batch result has neither response nor error")),
+    };
+    BatchResult { key, response }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::error::{self, ErrorKind};
+    use crate::log::Logger;
+    use crate::mock::MockServer;
+    use crate::usage::UsageConfig;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn client(server: &MockServer) -> LlmClient {
+        let config = Config {
+            version: 1,
+            provider: "gemini".to_string(),
+            timeouts: crate::config::Timeouts {
+                total_timeout: Some(5000),
+                ..Default::default()
+            },
+            gemini: Some(GeminiConfig {
+                key: "test-key".to_string(),
+                key_file: None,
+                key_command: None,
+                base_url: Some(server.url().to_string()),
+                model: "gemini-test".to_string(),
+                temperature: Some(0.5),
+                top_p: None,
+                top_k: None,
+                max_output_tokens: None,
+                thinking_budget: None,
+                thinking_level: None,
+            }),
+            prompt: HashMap::new(),
+            ..Default::default()
+        };
+        LlmClient::without_key(&config, &Logger::default())
+            .and_then(|client| client.with_key("test-key".to_string()))
+            .unwrap()
+    }
+
+    fn entry(key: &str, prompt: &str) -> BatchEntry {
+        BatchEntry {
+            key: key.to_string(),
+            prompt: prompt.to_string(),
+            overrides: GeminiOverrides::default(),
+        }
+    }
+
+    #[test]
+    fn test_batch_errors_do_not_contain_key() {
+        let server = MockServer::start(vec![(
+            "GET",
+            "/v1beta/batches/123",
+            403,
+            r#"{"error":{"code":403,"message":"key test-key is suspended"}}"#.to_string(),
+        )]);
+        let client = client(&server);
+        let err = client.batch_status("batches/123").unwrap_err();
+        assert!(!format!("{err:?}").contains("test-key"));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("x-goog-api-key"), Some("test-key"));
+        assert!(!requests[0].path.contains("key="));
+    }
+
+    #[test]
+    fn test_submit_batch() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "POST",
+            "/v1beta/models/gemini-test:batchGenerateContent",
+            200,
+            r#"{"name":"batches/123","metadata":{"state":"BATCH_STATE_PENDING"}}"#.to_string(),
+        )]);
+        let mut second = entry("b", "world");
+        second.overrides.top_k = Some(7);
+
+        let job = client(&server).submit_batch("nightly", &[entry("a", "hello"), second])?;
+        assert_eq!(job.name, "batches/123");
+        assert_eq!(job.state, "BATCH_STATE_PENDING");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].header("content-type"), Some("application/json"));
+        let body: Value = serde_json::from_str(&requests[0].body)?;
+        let inlined = &body["batch"]["input_config"]["requests"]["requests"];
+        assert_eq!(body["batch"]["display_name"], "nightly");
+        assert_eq!(inlined[0]["metadata"]["key"], "a");
+        assert_eq!(
+            inlined[0]["request"]["contents"][0]["parts"][0]["text"],
+            "hello"
+        );
+        assert_eq!(inlined[1]["request"]["generationConfig"]["topK"], 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_batch_rejects_model_override() {
+        let server = MockServer::start(vec![]);
+        let mut item = entry("a", "hello");
+        item.overrides.model = Some("other".to_string());
+        let result = client(&server).submit_batch("nightly", &[item]);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("overrides the model")
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn test_batch_usage_is_recorded_and_budgeted() -> Result<()> {
+        let server = MockServer::start(vec![
+            (
+                "POST",
+                "/v1beta/models/gemini-test:batchGenerateContent",
+                200,
+                r#"{"name":"batches/123","metadata":{"state":"BATCH_STATE_PENDING"}}"#.to_string(),
+            ),
+            (
+                "GET",
+                "/v1beta/batches/123",
+                200,
+                r#"{
+                    "name": "batches/123",
+                    "metadata": {"model": "models/gemini-2.5-flash", "state": "BATCH_STATE_SUCCEEDED"},
+                    "response": {"inlinedResponses": {"inlinedResponses": [
+                        {"metadata": {"key": "a"}, "response": {"candidates": [{"content": {"parts": [{"text": "one"}]}}], "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 2, "totalTokenCount": 6}}},
+                        {"metadata": {"key": "b"}, "response": {"candidates": [{"content": {"parts": [{"text": "two"}]}}], "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 1, "totalTokenCount": 4}}}
+                    ]}}
+                }"#
+                .to_string(),
+            ),
+        ]);
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("usage.jsonl");
+        let budget = UsageConfig {
+            daily_tokens: Some(10),
+            ..Default::default()
+        };
+        let client = client(&server).with_ledger(Ledger::new(path.clone(), budget));
+
+        client.submit_batch("nightly", &[entry("a", "hello"), entry("b", "world")])?;
+        client.batch_results("batches/123")?;
+        client.batch_results("batches/123")?;
+
+        let entries: Vec<Value> = fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<_>>()?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["status"], "pending");
+        assert_eq!(entries[1]["batch"], "batches/123");
+        assert_eq!(entries[1]["id"], entries[0]["id"]);
+        assert_eq!(entries[1]["total_tokens"], 0);
+        assert_eq!(entries[2]["model"], "gemini-2.5-flash");
+        assert_eq!(entries[2]["total_tokens"], 10);
+
+        let err = client
+            .submit_batch("nightly", &[entry("a", "hello")])
+            .unwrap_err();
+        assert_eq!(error::kind(&err), ErrorKind::Budget);
+        assert_eq!(server.requests().len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_status() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "GET",
+            "/v1beta/batches/123",
+            200,
+            r#"{"name":"batches/123","metadata":{"state":"BATCH_STATE_RUNNING","batchStats":{"requestCount":"3","successfulRequestCount":"1","pendingRequestCount":2}}}"#.to_string(),
+        )]);
+        let job = client(&server).batch_status("123")?;
+        assert_eq!(
+            job,
+            BatchJob {
+                name: "batches/123".to_string(),
+                state: "BATCH_STATE_RUNNING".to_string(),
+                request_count: 3,
+                succeeded_count: 1,
+                failed_count: 0,
+                pending_count: 2,
+            }
+        );
+        assert!(!job.is_succeeded());
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_results_inlined() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "GET",
+            "/v1beta/batches/123",
+            200,
+            r#"{
+                "name": "batches/123",
+                "metadata": {"state": "BATCH_STATE_SUCCEEDED"},
+                "done": true,
+                "response": {"inlinedResponses": {"inlinedResponses": [
+                    {"metadata": {"key": "a"}, "response": {"candidates": [{"content": {"parts": [{"text": "one"}]}, "finishReason": "STOP"}]}},
+                    {"metadata": {"key": "b"}, "error": {"code": 400, "message": "bad"}}
+                ]}}
+            }"#
+            .to_string(),
+        )]);
+        let results = client(&server).batch_results("batches/123")?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].key, "a");
+        assert_eq!(results[0].response.as_ref().unwrap().text, "one");
+        assert_eq!(results[1].key, "b");
+        assert!(results[1].response.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_results_file() -> Result<()> {
+        let server = MockServer::start(vec![
+            (
+                "GET",
+                "/v1beta/batches/123",
+                200,
+                r#"{"name":"batches/123","metadata":{"state":"BATCH_STATE_SUCCEEDED"},"response":{"responsesFile":"files/out"}}"#
+                    .to_string(),
+            ),
+            (
+                "GET",
+                "/download/v1beta/files/out:download",
+                200,
+                concat!(
+                    r#"{"key":"a","response":{"candidates":[{"content":{"parts":[{"text":"one"}]}}]}}"#,
+                    "\n"
+                )
+                .to_string(),
+            ),
+        ]);
+        let results = client(&server).batch_results("123")?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].key, "a");
+        assert_eq!(results[0].response.as_ref().unwrap().text, "one");
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_results_not_finished() {
+        let server = MockServer::start(vec![(
+            "GET",
+            "/v1beta/batches/123",
+            200,
+            r#"{"name":"batches/123","metadata":{"state":"BATCH_STATE_RUNNING"}}"#.to_string(),
+        )]);
+        let result = client(&server).batch_results("123");
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("has not succeeded")
+        );
+    }
+
+    #[test]
+    fn test_cancel_batch() -> Result<()> {
+        let server = MockServer::start(vec![(
+            "POST",
+            "/v1beta/batches/123:cancel",
+            200,
+            "{}".to_string(),
+        )]);
+        client(&server).cancel_batch("123")?;
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert!(requests[0].path.starts_with("/v1beta/batches/123:cancel"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_batch_api_error() {
+        let server = MockServer::start(vec![(
+            "POST",
+            "/v1beta/batches/123:cancel",
+            404,
+            r#"{"error":{"code":404,"message":"Batch not found"}}"#.to_string(),
+        )]);
+        let result = client(&server).cancel_batch("123");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "LLM API error 404: Batch not found"
+        );
+    }
+}
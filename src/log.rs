@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::{Map, Value, json};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::clock;
+
+pub const INFO: u8 = 1;
+pub const DEBUG: u8 = 2;
+pub const TRACE: u8 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Logger {
+    level: u8,
+    format: LogFormat,
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl Logger {
+    pub fn new(level: u8, format: LogFormat, path: Option<&Path>) -> Result<Self> {
+        let file = path
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open log file {}", path.display()))
+            })
+            .transpose()?;
+        Ok(Self {
+            level,
+            format,
+            file: file.map(|file| Arc::new(Mutex::new(file))),
+        })
+    }
+
+    pub fn enabled(&self, level: u8) -> bool {
+        self.level >= level
+    }
+
+    pub fn log(&self, level: u8, event: &str, message: &str) {
+        if self.enabled(level) {
+            self.write(event, message, Map::new());
+        }
+    }
+
+    pub fn timing(&self, step: &str, elapsed: Duration) {
+        if self.enabled(INFO) {
+            let ms = elapsed.as_millis() as u64;
+            let fields = Map::from_iter([
+                ("step".to_string(), json!(step)),
+                ("ms".to_string(), json!(ms)),
+            ]);
+            self.write("timing", &format!("{step} took {ms} ms"), fields);
+        }
+    }
+
+    fn write(&self, event: &str, message: &str, fields: Map<String, Value>) {
+        let line = match self.format {
+            LogFormat::Text => format!("[{event}] {message}"),
+            LogFormat::Json => {
+                let mut object = Map::from_iter([
+                    (
+                        "time".to_string(),
+                        json!(clock::timestamp(SystemTime::now())),
+                    ),
+                    ("event".to_string(), json!(event)),
+                    ("message".to_string(), json!(message)),
+                ]);
+                object.extend(fields);
+                Value::Object(object).to_string()
+            }
+        };
+        let _ = match self.file {
+            Some(ref file) => match file.lock() {
+                Ok(mut file) => writeln!(file, "{line}"),
+                Err(_) => Ok(()),
+            },
+            None => writeln!(io::stderr().lock(), "{line}"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_levels_and_json_lines() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("paip.log");
+        let logger = Logger::new(INFO, LogFormat::Json, Some(&path))?;
+
+        logger.timing("parse", Duration::from_millis(12));
+        logger.log(DEBUG, "response", "content-type: application/json");
+
+        let content = fs::read_to_string(&path)?;
+        let lines: Vec<Value> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<_>>()?;
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["event"], "timing");
+        assert_eq!(lines[0]["step"], "parse");
+        assert_eq!(lines[0]["ms"], 12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_file_is_appended() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("paip.log");
+        fs::write(&path, "old\n")?;
+        Logger::new(TRACE, LogFormat::Text, Some(&path))?.log(TRACE, "input", "hello");
+        assert_eq!(fs::read_to_string(&path)?, "old\n[input] hello\n");
+        Ok(())
+    }
+}
@@ -1,62 +1,375 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use clap::Parser;
 use std::fs::File;
 use std::io::{self, BufReader, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
 
+mod batch;
 mod cli;
+mod clock;
 mod config;
+mod each;
+mod error;
+mod in_place;
 mod llm;
+mod log;
+#[cfg(test)]
+mod mock;
+mod output;
+mod pool;
+mod prompts;
+mod records;
+mod redact;
+mod template;
+mod usage;
+
+use cli::{BatchArgs, BatchCommand, Cli, Command, ConfigCommand, PromptsCommand};
+use config::Timeouts;
+use each::Destination;
+use error::{Classify, ErrorKind};
+use llm::{LlmClient, LlmResponse};
+use log::Logger;
+use prompts::Prompt;
+use redact::Redactor;
+use template::Vars;
+use usage::Ledger;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let error_format = cli.error_format;
 
-use cli::Cli;
-use llm::LlmClient;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error::report(&e, error_format);
+            ExitCode::from(error::kind(&e).exit_code())
+        }
+    }
+}
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+fn run(cli: Cli) -> Result<()> {
+    anyhow::ensure!(
+        cli.dry_run.is_none() || cli.command.is_none(),
+        "--dry-run cannot be combined with a subcommand"
+    );
 
     if cli.init_config {
-        config::init_default()?;
+        config::init_default(cli.config.as_deref(), cli.force).classify(ErrorKind::Config)?;
         return Ok(());
     }
 
-    let config = config::load()?;
+    match cli.command {
+        Some(Command::Config {
+            command: ConfigCommand::Init { force },
+        }) => {
+            return config::init_interactive(cli.config.as_deref(), force)
+                .classify(ErrorKind::Config);
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Migrate,
+        }) => return config::migrate(cli.config.as_deref()).classify(ErrorKind::Config),
+        Some(Command::Config {
+            command: ConfigCommand::Set { ref key, ref value },
+        }) => return config::set(cli.config.as_deref(), key, value).classify(ErrorKind::Config),
+        Some(Command::Usage { by, ref since }) => return usage::report(by, since.as_deref()),
+        _ => {}
+    }
+
+    let logger = Logger::new(cli.verbose, cli.log_format, cli.log_file.as_deref())?;
+
+    let started = Instant::now();
+    let mut config = if cli.no_config {
+        config::load_defaults()
+    } else {
+        config::load(cli.config.as_deref())
+    }
+    .classify(ErrorKind::Config)?;
+    logger.timing("config", started.elapsed());
+
+    if let Some(mode) = cli.redact {
+        config.redact.mode = mode;
+    }
+
+    if cli.show_config_sources {
+        config::print_sources(&config);
+        return Ok(());
+    }
+
+    let vars = Vars {
+        user: cli.vars.iter().cloned().collect(),
+        files: cli.files.clone(),
+    };
+
+    match cli.command {
+        Some(Command::Batch(ref args)) => {
+            let client = new_client(&config, None, &cli, &logger)?;
+            return run_batch(&client, &config, &vars, args, &logger);
+        }
+        Some(Command::Prompts { ref command }) => {
+            return match command {
+                PromptsCommand::List => prompts::list(&config),
+                PromptsCommand::Show { name } => prompts::show(&config, name),
+            };
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Show,
+        }) => {
+            return config::show(&effective_config(&config, cli.prompt.as_deref())?);
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Get { ref key },
+        }) => {
+            return config::get(&effective_config(&config, cli.prompt.as_deref())?, key);
+        }
+        Some(Command::Config { .. }) => {
+            for path in &config.sources {
+                println!("{}: OK", path.display());
+            }
+            return Ok(());
+        }
+        Some(Command::Usage { .. }) | None => {}
+    }
+
+    let prompt_option = resolve_prompt(&config, cli.prompt.as_deref())?;
+
+    if let Some(ref prompt) = prompt_option {
+        if let Some(ref source) = prompt.source {
+            logger.log(log::INFO, "prompt", &format!("source {}", source.display()));
+        }
+        if let Some(ref description) = prompt.description {
+            logger.log(log::INFO, "prompt", description);
+        }
+    }
+
+    let redactor = Redactor::new(&config.redact).classify(ErrorKind::Config)?;
 
-    let prompt_text_option = resolve_prompt(&config, cli.prompt.as_deref())?;
+    let per_file = cli.output_file.as_deref().is_some_and(each::is_template);
+    anyhow::ensure!(
+        !per_file || cli.output == output::Format::Text,
+        "--output json cannot be combined with a per-file --output-file"
+    );
+    if cli.each || cli.in_place.is_some() || cli.diff || per_file {
+        let destination = destination(&cli);
+        let request = each::Request {
+            prompt: prompt_option.as_ref(),
+            message_text: cli.message.as_deref(),
+            vars: &vars,
+            jobs: cli.jobs,
+            destination: &destination,
+            redactor: &redactor,
+        };
+        let client = new_client(&config, prompt_option.as_ref(), &cli, &logger)?;
+        return each::run(&client, &cli.files, &request);
+    }
+    if let Some(ref path) = cli.output_file {
+        each::ensure_not_input(&each::canonical(&cli.files), Path::new(path))?;
+    }
 
-    let input_content = read(&cli.files, io::stdin())?;
+    let started = Instant::now();
+    let input_content = read(&cli.files, io::stdin())
+        .and_then(|input| redactor.apply("input", &input))
+        .classify(ErrorKind::Input)?;
+    logger.timing("input", started.elapsed());
+
+    if let Some(format) = record_format(&cli) {
+        let request = records::Request {
+            prompt: prompt_option.as_ref(),
+            message_text: cli.message.as_deref(),
+            vars: &vars,
+            jobs: cli.jobs,
+            format,
+        };
+        let client = new_client(&config, prompt_option.as_ref(), &cli, &logger)?;
+        return records::run(&client, &input_content, &request);
+    }
 
-    let input_full = assemble(
-        prompt_text_option.as_deref(),
+    let input_full = compose(
+        prompt_option.as_ref(),
         cli.message.as_deref(),
         &input_content,
-    );
+        &vars,
+    )?;
+
+    logger.log(log::TRACE, "input", &input_full);
 
-    if cli.verbose {
-        eprintln!("--- Full Input to LLM ---");
-        eprintln!("{input_full}");
-        eprintln!("-------------------------");
+    let client = new_client(&config, prompt_option.as_ref(), &cli, &logger)?;
+    if let Some(format) = cli.dry_run {
+        println!("{}", client.dry_run(&input_full, format)?);
+        return Ok(());
     }
+    let response = respond(&client, prompt_option.as_ref(), &input_full)?;
+    write_response(&cli, &client, &response)
+}
 
-    let client = LlmClient::new(&config, cli.verbose)?;
-    let response = client.send_request(&input_full)?;
+fn write_response(cli: &Cli, client: &LlmClient, response: &LlmResponse) -> Result<()> {
+    let text = match cli.output {
+        output::Format::Text => response.text.trim_end().to_string(),
+        output::Format::Json => output::envelope(client, cli.prompt.as_deref(), response)?,
+    };
+    if let Some(ref path) = cli.output_file {
+        output::write_atomic(Path::new(path), &format!("{text}\n"))?;
+    }
+    if cli.output_file.is_none() || cli.tee {
+        println!("{text}");
+    }
 
-    println!("{}", response.trim_end());
+    response.ensure_complete()
+}
 
-    Ok(())
+fn respond(client: &LlmClient, prompt: Option<&Prompt>, input_full: &str) -> Result<LlmResponse> {
+    let response = client.send_request(input_full)?;
+    if !response.is_truncated() && prompt.is_some_and(|p| p.output == prompts::OutputFormat::Json) {
+        serde_json::from_str::<serde_json::Value>(in_place::strip_fences(&response.text))
+            .context("Response is not valid JSON")
+            .classify(ErrorKind::InvalidOutput)?;
+    }
+    Ok(response)
 }
 
-fn resolve_prompt(config: &config::Config, prompt_name: Option<&str>) -> Result<Option<String>> {
+fn run_batch(
+    client: &LlmClient,
+    config: &config::Config,
+    vars: &Vars,
+    args: &BatchArgs,
+    logger: &Logger,
+) -> Result<()> {
+    match args.command {
+        Some(BatchCommand::Submit {
+            ref input,
+            ref name,
+        }) => {
+            let name = name.clone().unwrap_or_else(|| {
+                input
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "paip".to_string())
+            });
+            batch::submit(client, config, vars, input, &name)
+        }
+        Some(BatchCommand::Status { ref name }) => batch::status(client, name),
+        Some(BatchCommand::Fetch {
+            ref name,
+            ref output,
+        }) => batch::fetch(client, name, output),
+        Some(BatchCommand::Cancel { ref name }) => batch::cancel(client, name),
+        None => {
+            let (Some(input), Some(output)) = (&args.input, &args.output) else {
+                return Err(anyhow!("Batch input and --output are required"));
+            };
+            let request = batch::Request {
+                input,
+                output,
+                jobs: args.jobs,
+                retry_failed: args.retry_failed,
+                logger,
+            };
+            batch::run(client, config, vars, &request)
+        }
+    }
+}
+
+fn new_client(
+    config: &config::Config,
+    prompt: Option<&Prompt>,
+    cli: &Cli,
+    logger: &Logger,
+) -> Result<LlmClient> {
+    let mut config = config.clone();
+    if let Some(prompt) = prompt {
+        config.timeouts = config.timeouts.merged(&prompt.timeouts);
+    }
+    config.timeouts = config.timeouts.merged(&Timeouts {
+        connect_timeout: cli.connect_timeout,
+        read_timeout: cli.read_timeout,
+        total_timeout: cli.total_timeout,
+    });
+    let mut client = if cli.dry_run.is_some() {
+        LlmClient::without_key(&config, logger)
+    } else {
+        LlmClient::new(&config, logger)
+    }
+    .classify(ErrorKind::Config)?
+    .with_prompt_name(cli.prompt.as_deref());
+    if cli.dry_run.is_none()
+        && let Some(path) = usage::path()
+    {
+        client = client.with_ledger(Ledger::new(path, config.usage.clone()));
+    }
+    match prompt {
+        Some(prompt) => client.with_overrides(&prompt.overrides),
+        None => Ok(client),
+    }
+}
+
+fn effective_config(config: &config::Config, prompt_name: Option<&str>) -> Result<config::Config> {
+    let mut config = config.clone();
+    if let Some(prompt) = resolve_prompt(&config, prompt_name)? {
+        config.timeouts = config.timeouts.merged(&prompt.timeouts);
+        if let Some(ref mut gemini) = config.gemini {
+            gemini.apply(&prompt.overrides);
+        }
+    }
+    Ok(config)
+}
+
+fn resolve_prompt(config: &config::Config, prompt_name: Option<&str>) -> Result<Option<Prompt>> {
     prompt_name
         .map(|name| {
-            config
-                .prompt
-                .get(name)
-                .cloned()
-                .ok_or_else(|| anyhow!("Prompt '{}' not found in configuration.", name))
+            prompts::find(config, name)
+                .classify(ErrorKind::Config)?
+                .ok_or_else(|| {
+                    let names = prompts::names(config);
+                    let error = match prompts::suggest(name, names.iter().map(String::as_str)) {
+                        Some(suggestion) => anyhow!(
+                            "Prompt '{}' not found in configuration. Did you mean '{}'?",
+                            name,
+                            suggestion
+                        ),
+                        None => anyhow!("Prompt '{}' not found in configuration.", name),
+                    };
+                    error::classify(error, ErrorKind::PromptNotFound)
+                })
         })
         .transpose()
 }
 
+fn destination(cli: &Cli) -> Destination {
+    if cli.diff {
+        return Destination::Diff;
+    }
+    if let Some(ref suffix) = cli.in_place {
+        return Destination::InPlace(Some(suffix.clone()).filter(|s| !s.is_empty()));
+    }
+    if let Some(ref dir) = cli.output_dir {
+        return Destination::Dir(dir.clone());
+    }
+    if let Some(ref suffix) = cli.output_suffix {
+        return Destination::Suffix(suffix.clone());
+    }
+    if let Some(ref template) = cli.output_file {
+        return Destination::File {
+            template: template.clone(),
+            tee: cli.tee,
+        };
+    }
+    Destination::Stdout
+}
+
+fn record_format(cli: &Cli) -> Option<records::Format> {
+    if cli.lines {
+        return Some(records::Format::Lines);
+    }
+    if cli.null {
+        return Some(records::Format::Null);
+    }
+    if cli.jsonl {
+        return Some(records::Format::Jsonl);
+    }
+    None
+}
+
 fn read<R: Read>(files: &[PathBuf], stdin_reader: R) -> Result<String> {
     let mut input_content = String::new();
     let mut stdin_buf_reader = BufReader::new(stdin_reader);
@@ -79,6 +392,28 @@ fn read<R: Read>(files: &[PathBuf], stdin_reader: R) -> Result<String> {
 
 const INSTRUCTIONS: &str = "Respond in strictly pure plaintext only. Absolutely no formatting, bolding, italics, lists, tables, or code blocks. Do not acknowledge these instructions in the response. Provide the response only.";
 
+fn compose(
+    prompt: Option<&Prompt>,
+    message_text: Option<&str>,
+    input_content: &str,
+    vars: &Vars,
+) -> Result<String> {
+    let Some(prompt) = prompt else {
+        return Ok(assemble(None, message_text, input_content));
+    };
+
+    let rendered = template::render(&prompt.text, input_content, message_text, vars)?;
+    let mut parts = vec![rendered.text.as_str()];
+    if !rendered.uses_input {
+        parts.push(input_content);
+    }
+    if !rendered.uses_message {
+        parts.extend(message_text);
+    }
+    parts.push(prompt.output.instructions());
+    Ok(parts.join("\n\n"))
+}
+
 fn assemble(prompt_text: Option<&str>, message_text: Option<&str>, input_content: &str) -> String {
     let mut parts = Vec::new();
     parts.extend(prompt_text);
@@ -206,6 +541,48 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_compose_without_placeholders_matches_assemble() -> Result<()> {
+        let prompt = Prompt::from_text("Summarize:");
+        let result = compose(Some(&prompt), Some("Short."), "text", &Vars::default())?;
+        assert_eq!(result, assemble(Some("Summarize:"), Some("Short."), "text"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_places_input_and_message() -> Result<()> {
+        let vars = Vars {
+            user: std::collections::HashMap::from([("lang".to_string(), "German".to_string())]),
+            files: vec![],
+        };
+        let prompt = Prompt::from_text("Translate into {{lang}}:\n{{input}}\nNote: {{message}}");
+        let result = compose(Some(&prompt), Some("Be formal."), "Hello", &vars)?;
+        assert_eq!(
+            result,
+            format!("Translate into German:\nHello\nNote: Be formal.\n\n{INSTRUCTIONS}")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_uses_prompt_output_instructions() -> Result<()> {
+        let prompt = Prompt {
+            output: prompts::OutputFormat::Json,
+            ..Prompt::from_text("List the names.")
+        };
+        let result = compose(Some(&prompt), None, "text", &Vars::default())?;
+        assert!(result.ends_with(prompts::OutputFormat::Json.instructions()));
+        assert!(!result.contains(INSTRUCTIONS));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_message_without_prompt_is_literal() -> Result<()> {
+        let result = compose(None, Some("{{lang}}"), "text", &Vars::default())?;
+        assert_eq!(result, format!("text\n\n{{{{lang}}}}\n\n{INSTRUCTIONS}"));
+        Ok(())
+    }
+
     #[test]
     fn test_read_non_existent_file() {
         let files = vec![PathBuf::from("non_existent_file_12345.txt")];
@@ -231,12 +608,12 @@ mod tests {
         let config = crate::config::Config {
             version: crate::config::VERSION,
             provider: "p".to_string(),
-            timeout: 0,
             gemini: None,
             prompt,
+            ..Default::default()
         };
         let res = resolve_prompt(&config, Some("p1"))?;
-        assert_eq!(res, Some("text1".to_string()));
+        assert_eq!(res.map(|p| p.text), Some("text1".to_string()));
         Ok(())
     }
 
@@ -245,13 +622,29 @@ mod tests {
         let config = crate::config::Config {
             version: crate::config::VERSION,
             provider: "p".to_string(),
-            timeout: 0,
             gemini: None,
             prompt: std::collections::HashMap::new(),
+            ..Default::default()
         };
-        let res = resolve_prompt(&config, Some("p1"));
-        assert!(res.is_err());
-        assert!(res.unwrap_err().to_string().contains("not found"));
+        let err = resolve_prompt(&config, Some("p1")).unwrap_err();
+        assert!(err.to_string().contains("not found"));
+        assert_eq!(error::kind(&err), ErrorKind::PromptNotFound);
+    }
+
+    #[test]
+    fn test_resolve_prompt_not_found_suggests_name() {
+        let config = crate::config::Config {
+            prompt: std::collections::HashMap::from([(
+                "review".to_string(),
+                "Review.".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let res = resolve_prompt(&config, Some("reveiw"));
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Prompt 'reveiw' not found in configuration. Did you mean 'review'?"
+        );
     }
 
     #[test]
@@ -259,12 +652,141 @@ mod tests {
         let config = crate::config::Config {
             version: crate::config::VERSION,
             provider: "p".to_string(),
-            timeout: 0,
             gemini: None,
             prompt: std::collections::HashMap::new(),
+            ..Default::default()
         };
         let res = resolve_prompt(&config, None)?;
         assert!(res.is_none());
         Ok(())
     }
+
+    #[test]
+    fn test_truncated_response_is_written_and_exits_10() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("output.json");
+        let output_arg = output.to_string_lossy();
+        let cli = Cli::try_parse_from(["paip", "--output", "json", "-o", &output_arg])?;
+        let client = LlmClient::without_key(&config::load_defaults()?, &Logger::default())?;
+        let response = LlmResponse {
+            text: "partial".to_string(),
+            finish_reason: Some("MAX_TOKENS".to_string()),
+            ..Default::default()
+        };
+
+        let err = write_response(&cli, &client, &response).unwrap_err();
+        assert_eq!(error::kind(&err).exit_code(), 10);
+        let envelope: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&output)?)?;
+        assert_eq!(envelope["text"], "partial");
+        assert_eq!(envelope["finish_reason"], "MAX_TOKENS");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_does_not_resolve_the_key() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let marker = dir.path().join("key_command_ran");
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            include_str!("../config.toml").replace(
+                "key = \"YOUR_GEMINI_API_KEY\"",
+                &format!("key_command = \"touch '{}'\"", marker.display()),
+            ),
+        )?;
+        let input = dir.path().join("input.txt");
+        std::fs::write(&input, "hello")?;
+
+        let config_arg = config_path.to_string_lossy();
+        let input_arg = input.to_string_lossy();
+        run(Cli::try_parse_from([
+            "paip",
+            "--config",
+            &config_arg,
+            "--dry-run",
+            "-p",
+            "sum",
+            &input_arg,
+        ])?)?;
+        assert!(!marker.exists());
+
+        run(Cli::try_parse_from([
+            "paip",
+            "--no-config",
+            "--dry-run=curl",
+            &input_arg,
+        ])?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_file_cannot_overwrite_an_input() -> Result<()> {
+        let server = mock::MockServer::start(vec![]);
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            include_str!("../config.toml").replace(
+                "key = \"YOUR_GEMINI_API_KEY\"",
+                &format!("key = \"test-key\"\nbase_url = \"{}\"", server.url()),
+            ),
+        )?;
+        let input = dir.path().join("foo.rs");
+        std::fs::write(&input, "fn main() {}")?;
+
+        let config_arg = config_path.to_string_lossy();
+        let input_arg = input.to_string_lossy();
+        let alias = dir.path().join(".").join("foo.rs");
+        let alias_arg = alias.to_string_lossy();
+        let cli = Cli::try_parse_from([
+            "paip",
+            "--config",
+            &config_arg,
+            &input_arg,
+            "-o",
+            &alias_arg,
+        ])?;
+        let err = run(cli).unwrap_err();
+        assert!(err.to_string().contains("--in-place"), "{err}");
+        assert!(server.requests().is_empty());
+        assert_eq!(std::fs::read_to_string(&input)?, "fn main() {}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_is_rejected_with_subcommands() -> Result<()> {
+        let server = mock::MockServer::start(vec![]);
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            include_str!("../config.toml").replace(
+                "key = \"YOUR_GEMINI_API_KEY\"",
+                &format!("key = \"test-key\"\nbase_url = \"{}\"", server.url()),
+            ),
+        )?;
+        let input = dir.path().join("in.jsonl");
+        let output = dir.path().join("out.jsonl");
+        std::fs::write(&input, "{\"id\":\"a\",\"input\":\"hello\"}\n")?;
+
+        let config_arg = config_path.to_string_lossy();
+        let input_arg = input.to_string_lossy();
+        let output_arg = output.to_string_lossy();
+        for args in [
+            vec!["batch", &input_arg, "-o", &output_arg],
+            vec!["batch", "submit", &input_arg],
+            vec!["batch", "status", "batches/123"],
+        ] {
+            let cli = Cli::try_parse_from(
+                ["paip", "--config", &config_arg, "--dry-run"]
+                    .into_iter()
+                    .chain(args),
+            )?;
+            let err = run(cli).unwrap_err();
+            assert!(err.to_string().contains("--dry-run cannot be combined"));
+        }
+        assert!(server.requests().is_empty());
+        assert!(!output.exists());
+        Ok(())
+    }
 }
@@ -0,0 +1,125 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Route {
+    method: &'static str,
+    path: &'static str,
+    status: u16,
+    body: String,
+}
+
+pub struct MockServer {
+    url: String,
+    recorded: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl MockServer {
+    pub fn start(routes: Vec<(&'static str, &'static str, u16, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let url = format!("http://{}", listener.local_addr().expect("mock address"));
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let routes: Vec<Route> = routes
+            .into_iter()
+            .map(|(method, path, status, body)| Route {
+                method,
+                path,
+                status,
+                body,
+            })
+            .collect();
+
+        let recorded_server = Arc::clone(&recorded);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                handle(stream, &routes, &recorded_server);
+            }
+        });
+
+        Self { url, recorded }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.recorded.lock().expect("mock lock").clone()
+    }
+}
+
+fn handle(stream: TcpStream, routes: &[Route], recorded: &Mutex<Vec<Recorded>>) {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() || line.trim().is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.trim_end().split_once(':') {
+            let value = value.trim().to_string();
+            if key.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or(0);
+            }
+            headers.push((key.to_string(), value));
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let path_only = path.split('?').next().unwrap_or_default();
+    let route = routes
+        .iter()
+        .find(|r| r.method == method && r.path == path_only);
+    let (status, response) = match route {
+        Some(route) => (route.status, route.body.clone()),
+        None => (
+            404,
+            r#"{"error":{"code":404,"message":"not found"}}"#.to_string(),
+        ),
+    };
+
+    recorded.lock().expect("mock lock").push(Recorded {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+
+    let mut stream = stream;
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    );
+}
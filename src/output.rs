@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+use crate::llm::{LlmClient, LlmResponse, Usage};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    text: &'a str,
+    provider: &'a str,
+    model: &'a str,
+    prompt: Option<&'a str>,
+    finish_reason: Option<&'a str>,
+    usage: Option<&'a Usage>,
+    latency_ms: u128,
+    request_id: Option<&'a str>,
+}
+
+pub fn envelope(
+    client: &LlmClient,
+    prompt: Option<&str>,
+    response: &LlmResponse,
+) -> Result<String> {
+    let envelope = Envelope {
+        text: response.text.trim_end(),
+        provider: client.provider(),
+        model: client.model(),
+        prompt,
+        finish_reason: response.finish_reason.as_deref(),
+        usage: response.usage.as_ref(),
+        latency_ms: response.latency.as_millis(),
+        request_id: response.request_id.as_deref(),
+    };
+    Ok(serde_json::to_string(&envelope)?)
+}
+
+pub fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+
+    let mut file = NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create temporary file in {}", dir.display()))?;
+    file.write_all(content.as_bytes())?;
+    file.as_file().sync_all()?;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(file.path(), metadata.permissions())?;
+    }
+
+    file.persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::log::Logger;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_envelope() -> Result<()> {
+        let config: Config = toml::from_str(
+            "version = 1\nprovider = \"gemini\"\n[gemini]\nkey = \"k\"\nmodel = \"gemini-test\"\n",
+        )?;
+        let client =
+            LlmClient::without_key(&config, &Logger::default())?.with_key("k".to_string())?;
+        let response = LlmResponse {
+            text: "Hallo Welt\n".to_string(),
+            finish_reason: Some("STOP".to_string()),
+            usage: Some(Usage {
+                prompt_tokens: 3,
+                output_tokens: 2,
+                thinking_tokens: 0,
+                total_tokens: 5,
+            }),
+            request_id: Some("abc123".to_string()),
+            latency: Duration::from_millis(1234),
+        };
+
+        let value: serde_json::Value =
+            serde_json::from_str(&envelope(&client, Some("de"), &response)?)?;
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "text": "Hallo Welt",
+                "provider": "gemini",
+                "model": "gemini-test",
+                "prompt": "de",
+                "finish_reason": "STOP",
+                "usage": {
+                    "prompt_tokens": 3,
+                    "output_tokens": 2,
+                    "thinking_tokens": 0,
+                    "total_tokens": 5
+                },
+                "latency_ms": 1234,
+                "request_id": "abc123"
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_atomic_creates_and_replaces() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("nested").join("out.txt");
+
+        write_atomic(&path, "first")?;
+        assert_eq!(fs::read_to_string(&path)?, "first");
+
+        write_atomic(&path, "second")?;
+        assert_eq!(fs::read_to_string(&path)?, "second");
+        assert_eq!(fs::read_dir(path.parent().unwrap())?.count(), 1);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_preserves_permissions() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir()?;
+        let path = dir.path().join("script.sh");
+        fs::write(&path, "old")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+
+        write_atomic(&path, "new")?;
+        let mode = fs::metadata(&path)?.permissions().mode() & 0o777;
+        assert_eq!(mode, 0o755);
+        Ok(())
+    }
+}
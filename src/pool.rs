@@ -0,0 +1,143 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+pub fn run<T, R, W, D>(items: Vec<T>, jobs: usize, work: W, mut done: D) -> Result<()>
+where
+    T: Send,
+    R: Send,
+    W: Fn(T) -> R + Sync,
+    D: FnMut(usize, R) -> Result<()>,
+{
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let workers = jobs.max(1);
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let queue = &queue;
+            let work = &work;
+            scope.spawn(move || {
+                loop {
+                    let next = queue.lock().map(|mut items| items.next());
+                    let Ok(Some((index, item))) = next else {
+                        return;
+                    };
+                    if sender.send((index, work(item))).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (index, result) in receiver {
+            if let Err(e) = done(index, result) {
+                if let Ok(mut items) = queue.lock() {
+                    items.by_ref().for_each(drop);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn run_ordered<T, R, W, D>(items: Vec<T>, jobs: usize, work: W, mut done: D) -> Result<()>
+where
+    T: Send,
+    R: Send,
+    W: Fn(T) -> R + Sync,
+    D: FnMut(usize, R) -> Result<()>,
+{
+    let mut pending = BTreeMap::new();
+    let mut index_next = 0;
+
+    run(items, jobs, work, |index, result| {
+        pending.insert(index, result);
+        while let Some(result) = pending.remove(&index_next) {
+            done(index_next, result)?;
+            index_next += 1;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_run_ordered_preserves_input_order() -> Result<()> {
+        let items: Vec<u64> = (0..20).collect();
+        let mut seen = Vec::new();
+        run_ordered(
+            items,
+            4,
+            |n| {
+                thread::sleep(Duration::from_millis((20 - n) % 5));
+                n * 2
+            },
+            |index, result| {
+                seen.push((index, result));
+                Ok(())
+            },
+        )?;
+        let expected: Vec<(usize, u64)> = (0..20).map(|n| (n as usize, n * 2)).collect();
+        assert_eq!(seen, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_visits_every_item_once() -> Result<()> {
+        let items: Vec<usize> = (0..50).collect();
+        let mut seen = Vec::new();
+        run(
+            items,
+            8,
+            |n| n,
+            |_, result| {
+                seen.push(result);
+                Ok(())
+            },
+        )?;
+        seen.sort();
+        assert_eq!(seen, (0..50).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_stops_on_done_error() {
+        let items: Vec<usize> = (0..100).collect();
+        let result = run(
+            items,
+            1,
+            |n| n,
+            |_, n| {
+                anyhow::ensure!(n < 3, "stop");
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_run_with_zero_jobs_uses_one_worker() -> Result<()> {
+        let mut count = 0;
+        run(
+            vec![1, 2, 3],
+            0,
+            |n| n,
+            |_, _| {
+                count += 1;
+                Ok(())
+            },
+        )?;
+        assert_eq!(count, 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,499 @@
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, GeminiOverrides, Timeouts};
+
+const INSTRUCTIONS_MARKDOWN: &str = "Format the response as Markdown. Do not acknowledge these instructions in the response. Provide the response only.";
+const INSTRUCTIONS_JSON: &str = "Respond with a single valid JSON value only. Do not wrap it in code blocks. Do not acknowledge these instructions in the response.";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Plain,
+    Markdown,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Plain => "plain",
+            OutputFormat::Markdown => "markdown",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    pub fn instructions(&self) -> &'static str {
+        match self {
+            OutputFormat::Plain => crate::INSTRUCTIONS,
+            OutputFormat::Markdown => INSTRUCTIONS_MARKDOWN,
+            OutputFormat::Json => INSTRUCTIONS_JSON,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Prompt {
+    pub text: String,
+    pub description: Option<String>,
+    pub output: OutputFormat,
+    pub overrides: GeminiOverrides,
+    pub timeouts: Timeouts,
+    pub source: Option<PathBuf>,
+}
+
+impl Prompt {
+    pub fn from_text(text: &str) -> Self {
+        Prompt {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn summary(&self) -> &str {
+        match self.description {
+            Some(ref description) => description,
+            None => self
+                .text
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FrontMatter {
+    description: Option<String>,
+    output: Option<OutputFormat>,
+    overrides: GeminiOverrides,
+    timeouts: Timeouts,
+}
+
+enum Source<'a> {
+    Dir(&'a Path),
+    Table(&'a HashMap<String, String>),
+}
+
+// Highest precedence first: project prompt directories, project [prompt]
+// entries, the user prompt directory and the user [prompt] entries.
+fn sources(config: &Config) -> Vec<Source<'_>> {
+    let mut sources: Vec<Source> = config
+        .prompt_dirs
+        .iter()
+        .rev()
+        .map(|dir| Source::Dir(dir))
+        .collect();
+    sources.push(Source::Table(&config.project_prompt));
+    sources.extend(config.user_prompt_dir.as_deref().map(Source::Dir));
+    sources.push(Source::Table(&config.prompt));
+    sources
+}
+
+pub fn find(config: &Config, name: &str) -> Result<Option<Prompt>> {
+    let is_file_name = !name.is_empty() && !name.contains(['/', '\\']) && name != "..";
+    for source in sources(config) {
+        match source {
+            Source::Dir(dir) if is_file_name => {
+                let path = dir.join(format!("{name}.md"));
+                if path.is_file() {
+                    return load_file(&path).map(Some);
+                }
+            }
+            Source::Dir(_) => {}
+            Source::Table(table) => {
+                if let Some(text) = table.get(name) {
+                    return Ok(Some(Prompt::from_text(text)));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn all(config: &Config) -> Result<BTreeMap<String, Prompt>> {
+    let mut prompts = BTreeMap::new();
+    for source in sources(config).into_iter().rev() {
+        match source {
+            Source::Dir(dir) => {
+                for (name, path) in prompt_files(dir)? {
+                    prompts.insert(name, load_file(&path)?);
+                }
+            }
+            Source::Table(table) => prompts.extend(
+                table
+                    .iter()
+                    .map(|(name, text)| (name.clone(), Prompt::from_text(text))),
+            ),
+        }
+    }
+    Ok(prompts)
+}
+
+fn prompt_files(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", dir.display(), e)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("md") || !path.is_file() {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+            files.push((name.to_string(), path.clone()));
+        }
+    }
+    Ok(files)
+}
+
+pub fn list(config: &Config) -> Result<()> {
+    let prompts = all(config)?;
+    let width = prompts.keys().map(|name| name.len()).max().unwrap_or(0);
+    for (name, prompt) in &prompts {
+        println!("{name:width$}  {}", prompt.summary());
+    }
+    Ok(())
+}
+
+pub fn show(config: &Config, name: &str) -> Result<()> {
+    let prompt = crate::resolve_prompt(config, Some(name))?
+        .ok_or_else(|| anyhow!("Prompt '{}' not found in configuration.", name))?;
+
+    println!("name: {name}");
+    match prompt.source {
+        Some(ref path) => println!("source: {}", path.display()),
+        None => println!("source: configuration file"),
+    }
+    if let Some(ref description) = prompt.description {
+        println!("description: {description}");
+    }
+    println!("output: {}", prompt.output.as_str());
+    for (key, value) in override_fields(&prompt.overrides) {
+        println!("{key}: {value}");
+    }
+    for (key, value) in toml::Table::try_from(prompt.timeouts)? {
+        println!("{key}: {value}");
+    }
+    println!();
+    println!("{}", prompt.text.trim_end());
+    Ok(())
+}
+
+pub fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let threshold = (name.chars().count() / 3).max(1);
+    candidates
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(d, _)| *d <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+pub fn names(config: &Config) -> Vec<String> {
+    let mut names = Vec::new();
+    for source in sources(config) {
+        match source {
+            Source::Dir(dir) => names.extend(
+                prompt_files(dir)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, _)| name),
+            ),
+            Source::Table(table) => names.extend(table.keys().cloned()),
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+fn override_fields(overrides: &GeminiOverrides) -> Vec<(&'static str, String)> {
+    let mut fields = Vec::new();
+    if let Some(ref model) = overrides.model {
+        fields.push(("model", model.clone()));
+    }
+    if let Some(temperature) = overrides.temperature {
+        fields.push(("temperature", temperature.to_string()));
+    }
+    if let Some(top_p) = overrides.top_p {
+        fields.push(("top_p", top_p.to_string()));
+    }
+    if let Some(top_k) = overrides.top_k {
+        fields.push(("top_k", top_k.to_string()));
+    }
+    if let Some(max_output_tokens) = overrides.max_output_tokens {
+        fields.push(("max_output_tokens", max_output_tokens.to_string()));
+    }
+    if let Some(thinking_budget) = overrides.thinking_budget {
+        fields.push(("thinking_budget", thinking_budget.to_string()));
+    }
+    if let Some(ref thinking_level) = overrides.thinking_level {
+        fields.push(("thinking_level", thinking_level.clone()));
+    }
+    fields
+}
+
+pub fn load_file(path: &Path) -> Result<Prompt> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (front_matter, text) = split_front_matter(&content);
+    let meta = match front_matter {
+        Some(front_matter) => parse_front_matter(front_matter)
+            .with_context(|| format!("Invalid front matter in {}", path.display()))?,
+        None => FrontMatter::default(),
+    };
+    Ok(Prompt {
+        text: text.trim().to_string(),
+        description: meta.description,
+        output: meta.output.unwrap_or_default(),
+        overrides: meta.overrides,
+        timeouts: meta.timeouts,
+        source: Some(path.to_path_buf()),
+    })
+}
+
+fn split_front_matter(content: &str) -> (Option<&str>, &str) {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return (None, content);
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, content)
+}
+
+fn parse_front_matter(front_matter: &str) -> Result<FrontMatter> {
+    let mut table = toml::Table::new();
+    for line in front_matter.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected 'key: value', got '{line}'"))?;
+        table.insert(key.trim().to_string(), front_matter_value(value.trim()));
+    }
+
+    let description = table
+        .remove("description")
+        .map(|v| v.try_into())
+        .transpose()
+        .map_err(|e| anyhow!("description: {}", e))?;
+    let output = table
+        .remove("output")
+        .map(|v| v.try_into())
+        .transpose()
+        .map_err(|e| anyhow!("output: {}", e))?;
+    let mut timeouts = toml::Table::new();
+    for key in ["connect_timeout", "read_timeout", "total_timeout"] {
+        if let Some(value) = table.remove(key) {
+            timeouts.insert(key.to_string(), value);
+        }
+    }
+    let timeouts = timeouts.try_into().map_err(|e| anyhow!("{}", e))?;
+    let overrides = table.try_into().map_err(|e| anyhow!("{}", e))?;
+    Ok(FrontMatter {
+        description,
+        output,
+        overrides,
+        timeouts,
+    })
+}
+
+fn front_matter_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_file_with_front_matter() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("commit.md");
+        fs::write(
+            &path,
+            "---\ndescription: Conventional commit message\nmodel: gemini-2.5-pro\ntemperature: 0.2\noutput: markdown\ntotal_timeout: 300000\n---\n\nWrite a commit message.\n",
+        )?;
+
+        let prompt = load_file(&path)?;
+        assert_eq!(prompt.text, "Write a commit message.");
+        assert_eq!(
+            prompt.description.as_deref(),
+            Some("Conventional commit message")
+        );
+        assert_eq!(prompt.output, OutputFormat::Markdown);
+        assert_eq!(prompt.overrides.model.as_deref(), Some("gemini-2.5-pro"));
+        assert_eq!(prompt.overrides.temperature, Some(0.2));
+        assert_eq!(prompt.timeouts.total_timeout, Some(300000));
+        assert_eq!(prompt.source, Some(path));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_file_without_front_matter() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("plain.md");
+        fs::write(&path, "Just text.\n---\nwith a rule\n")?;
+
+        let prompt = load_file(&path)?;
+        assert_eq!(prompt.text, "Just text.\n---\nwith a rule");
+        assert_eq!(prompt.output, OutputFormat::Plain);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_file_rejects_unknown_front_matter_key() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("bad.md");
+        fs::write(&path, "---\nmodle: x\n---\nText\n")?;
+
+        let err = load_file(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("modle"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_precedence() -> Result<()> {
+        let user = tempdir()?;
+        let project = tempdir()?;
+        fs::write(user.path().join("sum.md"), "User sum.")?;
+        fs::write(user.path().join("review.md"), "User review.")?;
+        fs::write(user.path().join("commit.md"), "User commit.")?;
+        fs::write(project.path().join("sum.md"), "Project sum.")?;
+
+        let config = Config {
+            prompt: HashMap::from([
+                ("sum".to_string(), "Config sum.".to_string()),
+                ("proof".to_string(), "Config proof.".to_string()),
+                ("commit".to_string(), "Project commit.".to_string()),
+            ]),
+            project_prompt: HashMap::from([("commit".to_string(), "Project commit.".to_string())]),
+            user_prompt_dir: Some(user.path().to_path_buf()),
+            prompt_dirs: vec![project.path().to_path_buf()],
+            ..Default::default()
+        };
+
+        let text = |name| find(&config, name).map(|p| p.map(|p| p.text));
+        assert_eq!(text("sum")?.as_deref(), Some("Project sum."));
+        assert_eq!(text("review")?.as_deref(), Some("User review."));
+        assert_eq!(text("commit")?.as_deref(), Some("Project commit."));
+        assert_eq!(text("proof")?.as_deref(), Some("Config proof."));
+        assert_eq!(text("missing")?, None);
+        assert_eq!(all(&config)?["commit"].text, "Project commit.");
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_merges_sources() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join("review.md"),
+            "---\ndescription: Code review\n---\nReview.",
+        )?;
+        fs::write(dir.path().join("notes.txt"), "ignored")?;
+
+        let config = Config {
+            prompt: HashMap::from([
+                ("review".to_string(), "Old review.".to_string()),
+                (
+                    "sum".to_string(),
+                    "\nSummarize the following.\nBriefly.".to_string(),
+                ),
+            ]),
+            user_prompt_dir: Some(dir.path().to_path_buf()),
+            prompt_dirs: vec![dir.path().join("missing")],
+            ..Default::default()
+        };
+
+        let prompts = all(&config)?;
+        assert_eq!(prompts.keys().collect::<Vec<_>>(), vec!["review", "sum"]);
+        assert_eq!(prompts["review"].summary(), "Code review");
+        assert_eq!(prompts["sum"].summary(), "Summarize the following.");
+        assert_eq!(names(&config), vec!["review", "sum"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("review", "review"), 0);
+        assert_eq!(distance("reveiw", "review"), 2);
+        assert_eq!(distance("sum", "summ"), 1);
+        assert_eq!(distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let candidates = ["commit", "review", "sum", "slack"];
+        assert_eq!(suggest("comit", candidates.into_iter()), Some("commit"));
+        assert_eq!(suggest("reviw", candidates.into_iter()), Some("review"));
+        assert_eq!(suggest("translate", candidates.into_iter()), None);
+    }
+
+    #[test]
+    fn test_override_fields() {
+        let overrides = GeminiOverrides {
+            model: Some("m".to_string()),
+            top_k: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            override_fields(&overrides),
+            vec![("model", "m".to_string()), ("top_k", "3".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_front_matter_value_types() {
+        assert_eq!(front_matter_value("0.5"), toml::Value::Float(0.5));
+        assert_eq!(front_matter_value("64"), toml::Value::Integer(64));
+        assert_eq!(
+            front_matter_value("\"quoted\""),
+            toml::Value::String("quoted".to_string())
+        );
+        assert_eq!(
+            front_matter_value("bare words"),
+            toml::Value::String("bare words".to_string())
+        );
+    }
+}
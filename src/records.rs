@@ -0,0 +1,195 @@
+use anyhow::{Result, anyhow};
+use serde_json::{Value, json};
+use std::io::{self, Write};
+
+use crate::error::{self, Classify};
+use crate::llm::{LlmClient, LlmResponse};
+use crate::pool;
+use crate::prompts::Prompt;
+use crate::template::Vars;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Lines,
+    Null,
+    Jsonl,
+}
+
+pub struct Request<'a> {
+    pub prompt: Option<&'a Prompt>,
+    pub message_text: Option<&'a str>,
+    pub vars: &'a Vars,
+    pub jobs: usize,
+    pub format: Format,
+}
+
+pub fn run(client: &LlmClient, input_content: &str, request: &Request) -> Result<()> {
+    let records = split(input_content, request.format);
+    let total = records.iter().filter(|r| !r.trim().is_empty()).count();
+    let mut failed = Vec::new();
+    let mut stdout = io::stdout().lock();
+
+    pool::run_ordered(
+        records,
+        request.jobs,
+        |record| process(client, record, request),
+        |index, result| {
+            let complete = match result {
+                Ok(Some(ref response)) => response.ensure_complete(),
+                Ok(None) => Ok(()),
+                Err(ref e) => Err(anyhow!("{e:#}")).classify(error::kind(e)),
+            };
+            if let Err(ref e) = complete {
+                failed.push(error::kind(e));
+                eprintln!("Error processing record {}: {e:#}", index + 1);
+            }
+            let result = result.map(|response| response.map(|response| response.text));
+            let Some(output) = format_output(request.format, index, result) else {
+                return Ok(());
+            };
+            stdout.write_all(output.as_bytes())?;
+            stdout.flush()?;
+            Ok(())
+        },
+    )?;
+
+    error::failures(&failed, total, "records")
+}
+
+fn split(input_content: &str, format: Format) -> Vec<&str> {
+    let delimiter = match format {
+        Format::Lines | Format::Jsonl => '\n',
+        Format::Null => '\0',
+    };
+    let input_content = input_content
+        .strip_suffix(delimiter)
+        .unwrap_or(input_content);
+    if input_content.is_empty() {
+        return Vec::new();
+    }
+    input_content
+        .split(delimiter)
+        .map(|r| r.strip_suffix('\r').unwrap_or(r))
+        .collect()
+}
+
+fn process(client: &LlmClient, record: &str, request: &Request) -> Result<Option<LlmResponse>> {
+    if record.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let input_content = match request.format {
+        Format::Jsonl => record_text(record)?,
+        Format::Lines | Format::Null => record.to_string(),
+    };
+    let input_full = crate::compose(
+        request.prompt,
+        request.message_text,
+        &input_content,
+        request.vars,
+    )?;
+    client.send_request(&input_full).map(Some)
+}
+
+fn record_text(record: &str) -> Result<String> {
+    let value: Value =
+        serde_json::from_str(record).map_err(|e| anyhow!("Invalid JSON record: {}", e))?;
+    match value {
+        Value::String(text) => Ok(text),
+        other => Ok(other.to_string()),
+    }
+}
+
+fn format_output(format: Format, index: usize, result: Result<Option<String>>) -> Option<String> {
+    match (format, result) {
+        (Format::Jsonl, Ok(None)) => None,
+        (Format::Jsonl, Ok(Some(text))) => Some(format!(
+            "{}\n",
+            json!({ "index": index, "output": text.trim_end() })
+        )),
+        (Format::Jsonl, Err(e)) => Some(format!(
+            "{}\n",
+            json!({ "index": index, "error": format!("{e:#}") })
+        )),
+        (Format::Lines, result) => {
+            let text = result.ok().flatten().unwrap_or_default();
+            Some(format!("{}\n", single_line(&text)))
+        }
+        (Format::Null, result) => {
+            let text = result.ok().flatten().unwrap_or_default();
+            Some(format!("{}\0", text.trim_end()))
+        }
+    }
+}
+
+fn single_line(text: &str) -> String {
+    text.trim()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_lines() {
+        assert_eq!(
+            split("a\nb\r\n\nc\n", Format::Lines),
+            vec!["a", "b", "", "c"]
+        );
+    }
+
+    #[test]
+    fn test_split_null() {
+        assert_eq!(split("a\nb\0c\0", Format::Null), vec!["a\nb", "c"]);
+    }
+
+    #[test]
+    fn test_split_empty() {
+        assert!(split("", Format::Lines).is_empty());
+        assert!(split("\n", Format::Lines).is_empty());
+    }
+
+    #[test]
+    fn test_record_text_string_and_object() -> Result<()> {
+        assert_eq!(record_text(r#""hello""#)?, "hello");
+        assert_eq!(record_text(r#"{"a":1}"#)?, r#"{"a":1}"#);
+        assert!(record_text("{oops").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_output_lines_joins_multiline_response() {
+        let output = format_output(Format::Lines, 0, Ok(Some("one\n\ntwo\n".to_string())));
+        assert_eq!(output, Some("one two\n".to_string()));
+    }
+
+    #[test]
+    fn test_format_output_lines_keeps_position_on_error() {
+        let output = format_output(Format::Lines, 3, Err(anyhow!("boom")));
+        assert_eq!(output, Some("\n".to_string()));
+    }
+
+    #[test]
+    fn test_format_output_null() {
+        let output = format_output(Format::Null, 0, Ok(Some("a\nb\n".to_string())));
+        assert_eq!(output, Some("a\nb\0".to_string()));
+    }
+
+    #[test]
+    fn test_format_output_jsonl() {
+        let output = format_output(Format::Jsonl, 2, Ok(Some("positive".to_string())));
+        let value: Value = serde_json::from_str(output.unwrap().trim_end()).unwrap();
+        assert_eq!(value, json!({ "index": 2, "output": "positive" }));
+
+        let output = format_output(Format::Jsonl, 4, Err(anyhow!("boom")));
+        let value: Value = serde_json::from_str(output.unwrap().trim_end()).unwrap();
+        assert_eq!(value, json!({ "index": 4, "error": "boom" }));
+
+        assert_eq!(format_output(Format::Jsonl, 5, Ok(None)), None);
+    }
+}
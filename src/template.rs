@@ -0,0 +1,250 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+
+use crate::clock;
+
+const BUILTINS: [&str; 3] = ["input", "message", "date"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    pub user: HashMap<String, String>,
+    pub files: Vec<PathBuf>,
+}
+
+impl Vars {
+    pub fn with_files(&self, files: Vec<PathBuf>) -> Self {
+        Vars {
+            user: self.user.clone(),
+            files,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Rendered {
+    pub text: String,
+    pub uses_input: bool,
+    pub uses_message: bool,
+}
+
+pub fn render(
+    template: &str,
+    input_content: &str,
+    message_text: Option<&str>,
+    vars: &Vars,
+) -> Result<Rendered> {
+    let mut text = String::with_capacity(template.len());
+    let mut uses_input = false;
+    let mut uses_message = false;
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        if rest[start..].starts_with("{{{{") {
+            text.push_str(&rest[..start + 2]);
+            rest = &rest[start + 4..];
+            continue;
+        }
+        let Some(length) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        text.push_str(&rest[..start]);
+        let name = rest[start + 2..start + 2 + length].trim();
+        match name {
+            "input" => {
+                uses_input = true;
+                text.push_str(input_content);
+            }
+            "message" => {
+                uses_message = true;
+                text.push_str(message_text.unwrap_or_default());
+            }
+            _ => text.push_str(&lookup(name, vars)?),
+        }
+        rest = &rest[start + 2 + length + 2..];
+    }
+    text.push_str(rest);
+
+    Ok(Rendered {
+        text,
+        uses_input,
+        uses_message,
+    })
+}
+
+fn lookup(name: &str, vars: &Vars) -> Result<String> {
+    if name == "date" {
+        return Ok(clock::today());
+    }
+    if let Some(key) = name.strip_prefix("env.") {
+        return env::var(key)
+            .map_err(|_| anyhow!("Template variable '{{{{{name}}}}}': {key} is not set"));
+    }
+    if let Some(field) = name.strip_prefix("file.") {
+        return file_field(field, vars);
+    }
+    vars.user.get(name).cloned().ok_or_else(|| {
+        anyhow!("Template variable '{{{{{name}}}}}' is not set. Pass it with --var {name}=VALUE.")
+    })
+}
+
+fn file_field(field: &str, vars: &Vars) -> Result<String> {
+    let values: Vec<String> = vars
+        .files
+        .iter()
+        .filter(|path| path.to_str() != Some("-"))
+        .map(|path| {
+            let value = match field {
+                "path" => Some(path.as_os_str()),
+                "name" => path.file_name(),
+                "stem" => path.file_stem(),
+                "ext" => path.extension(),
+                _ => return Err(anyhow!("Unknown template variable '{{{{file.{field}}}}}'")),
+            };
+            Ok(value.unwrap_or_default().to_string_lossy().into_owned())
+        })
+        .collect::<Result<_>>()?;
+    Ok(values.join(" "))
+}
+
+pub fn parse_var(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got '{s}'"))?;
+    let name = name.trim();
+    if name.is_empty() || name.contains('.') || BUILTINS.contains(&name) {
+        return Err(format!("'{name}' cannot be used as a variable name"));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(user: &[(&str, &str)], files: &[&str]) -> Vars {
+        Vars {
+            user: user
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            files: files.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    #[test]
+    fn test_render_without_placeholders() -> Result<()> {
+        let rendered = render("Summarize the following.", "x", None, &Vars::default())?;
+        assert_eq!(rendered.text, "Summarize the following.");
+        assert!(!rendered.uses_input);
+        assert!(!rendered.uses_message);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_user_var() -> Result<()> {
+        let rendered = render(
+            "Translate the following into {{ lang }}.",
+            "x",
+            None,
+            &vars(&[("lang", "German")], &[]),
+        )?;
+        assert_eq!(rendered.text, "Translate the following into German.");
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_missing_var() {
+        let result = render("Into {{lang}}.", "x", None, &Vars::default());
+        assert!(result.unwrap_err().to_string().contains("--var lang=VALUE"));
+    }
+
+    #[test]
+    fn test_render_input_and_message() -> Result<()> {
+        let rendered = render(
+            "<doc>{{input}}</doc> {{message}}",
+            "body",
+            Some("note"),
+            &Vars::default(),
+        )?;
+        assert_eq!(rendered.text, "<doc>body</doc> note");
+        assert!(rendered.uses_input);
+        assert!(rendered.uses_message);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_input_is_not_expanded() -> Result<()> {
+        let rendered = render("{{input}}", "{{lang}}", None, &Vars::default())?;
+        assert_eq!(rendered.text, "{{lang}}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_file_fields() -> Result<()> {
+        let vars = vars(&[], &["src/main.rs", "-"]);
+        let rendered = render(
+            "{{file.path}} {{file.name}} {{file.stem}} {{file.ext}}",
+            "",
+            None,
+            &vars,
+        )?;
+        assert_eq!(rendered.text, "src/main.rs main.rs main rs");
+        assert!(render("{{file.size}}", "", None, &vars).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_env_and_date() -> Result<()> {
+        let rendered = render("{{env.PATH}}", "", None, &Vars::default())?;
+        assert_eq!(rendered.text, env::var("PATH")?);
+
+        let rendered = render("{{date}}", "", None, &Vars::default())?;
+        assert_eq!(rendered.text, clock::today());
+
+        let result = render(
+            "{{env.PAIP_TEST_UNSET_VARIABLE}}",
+            "",
+            None,
+            &Vars::default(),
+        );
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_escaped_braces() -> Result<()> {
+        let rendered = render(
+            "Keep {{{{name}} and {{{{ input }} for {{lang}}.",
+            "x",
+            None,
+            &vars(&[("lang", "Jinja")], &[]),
+        )?;
+        assert_eq!(rendered.text, "Keep {{name}} and {{ input }} for Jinja.");
+        assert!(!rendered.uses_input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_unterminated_placeholder() -> Result<()> {
+        let rendered = render("a {{b", "", None, &Vars::default())?;
+        assert_eq!(rendered.text, "a {{b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("lang=Brazilian Portuguese"),
+            Ok(("lang".to_string(), "Brazilian Portuguese".to_string()))
+        );
+        assert_eq!(
+            parse_var("eq=a=b"),
+            Ok(("eq".to_string(), "a=b".to_string()))
+        );
+        assert!(parse_var("lang").is_err());
+        assert!(parse_var("input=x").is_err());
+        assert!(parse_var("env.X=x").is_err());
+    }
+}
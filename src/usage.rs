@@ -0,0 +1,636 @@
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clock;
+use crate::error::{self, ErrorKind};
+use crate::llm::{LlmResponse, Usage};
+
+// USD per million tokens, matched by the longest model name prefix.
+// Thinking tokens are billed as output.
+const PRICES: [(&str, Price); 6] = [
+    ("gemini-3-pro", Price::new(2.0, 12.0)),
+    ("gemini-2.5-pro", Price::new(1.25, 10.0)),
+    ("gemini-2.5-flash", Price::new(0.3, 2.5)),
+    ("gemini-2.5-flash-lite", Price::new(0.1, 0.4)),
+    ("gemini-2.0-flash", Price::new(0.1, 0.4)),
+    ("gemini-2.0-flash-lite", Price::new(0.075, 0.3)),
+];
+const BATCH_DISCOUNT: f64 = 0.5;
+const PENDING: &str = "pending";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl Price {
+    const fn new(input: f64, output: f64) -> Self {
+        Self { input, output }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct UsageConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_cost: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_cost: Option<f64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub prices: HashMap<String, Price>,
+}
+
+impl UsageConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == UsageConfig::default()
+    }
+
+    fn price(&self, model: &str) -> Option<Price> {
+        if let Some(price) = self.prices.get(model) {
+            return Some(*price);
+        }
+        PRICES
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, price)| *price)
+    }
+
+    fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        let price = self.price(model)?;
+        let output = f64::from(usage.output_tokens) + f64::from(usage.thinking_tokens);
+        Some((f64::from(usage.prompt_tokens) * price.input + output * price.output) / 1e6)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: String,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    pub model: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+    #[serde(default)]
+    pub cost: Option<f64>,
+    pub latency_ms: u64,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GroupBy {
+    Day,
+    Model,
+    Prompt,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ledger {
+    path: PathBuf,
+    config: UsageConfig,
+}
+
+impl Ledger {
+    pub fn new(path: PathBuf, config: UsageConfig) -> Self {
+        Self { path, config }
+    }
+
+    fn has_budget(&self) -> bool {
+        let config = &self.config;
+        config.daily_tokens.is_some()
+            || config.monthly_tokens.is_some()
+            || config.daily_cost.is_some()
+            || config.monthly_cost.is_some()
+    }
+
+    fn lock(&self) -> Result<File> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let path = self.path.with_extension("lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("Failed to lock {}", path.display()))?;
+        Ok(file)
+    }
+
+    // A pending entry counts the estimated prompt tokens of a request that
+    // has not been recorded yet, so parallel requests see each other.
+    pub fn reserve(
+        &self,
+        model: &str,
+        prompt: Option<&str>,
+        input_len: usize,
+        now: SystemTime,
+    ) -> Result<Option<String>> {
+        if !self.has_budget() {
+            return Ok(None);
+        }
+        let _lock = self.lock()?;
+        self.check_budget(now)?;
+
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let id = format!(
+            "{}-{}-{}",
+            process::id(),
+            since_epoch.as_nanos(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        let tokens = u32::try_from(input_len / 4).unwrap_or(u32::MAX);
+        let usage = Usage {
+            prompt_tokens: tokens,
+            total_tokens: tokens,
+            ..Default::default()
+        };
+        self.append(Entry {
+            time: clock::timestamp(now),
+            prompt: prompt.map(str::to_string),
+            model: model.to_string(),
+            cost: self.config.cost(model, &usage),
+            usage,
+            latency_ms: 0,
+            status: PENDING.to_string(),
+            batch: None,
+            id: Some(id.clone()),
+        })?;
+        Ok(Some(id))
+    }
+
+    pub fn check_budget(&self, now: SystemTime) -> Result<()> {
+        if !self.has_budget() {
+            return Ok(());
+        }
+        let config = &self.config;
+
+        let today = clock::date(now);
+        let entries = outstanding(read(&self.path)?);
+        let (day_tokens, day_cost) = totals(&entries, &today);
+        let (month_tokens, month_cost) = totals(&entries, &today[..7]);
+
+        let exceeded = if let Some(limit) = config.daily_tokens.filter(|l| day_tokens >= *l) {
+            format!("Daily token budget of {limit} reached ({day_tokens} tokens used today)")
+        } else if let Some(limit) = config.monthly_tokens.filter(|l| month_tokens >= *l) {
+            format!(
+                "Monthly token budget of {limit} reached ({month_tokens} tokens used this month)"
+            )
+        } else if let Some(limit) = config.daily_cost.filter(|l| day_cost >= *l) {
+            format!("Daily cost budget of ${limit:.2} reached (${day_cost:.2} estimated today)")
+        } else if let Some(limit) = config.monthly_cost.filter(|l| month_cost >= *l) {
+            format!(
+                "Monthly cost budget of ${limit:.2} reached (${month_cost:.2} estimated this month)"
+            )
+        } else {
+            return Ok(());
+        };
+        Err(error::classify(
+            anyhow!("{exceeded}, refusing to send the request"),
+            ErrorKind::Budget,
+        ))
+    }
+
+    pub fn record(
+        &self,
+        model: &str,
+        prompt: Option<&str>,
+        result: &Result<LlmResponse>,
+        latency: Duration,
+        id: Option<String>,
+    ) -> Result<()> {
+        let usage = match result {
+            Ok(response) => response.usage.clone().unwrap_or_default(),
+            Err(_) => Usage::default(),
+        };
+        self.append(Entry {
+            time: clock::timestamp(SystemTime::now()),
+            prompt: prompt.map(str::to_string),
+            model: model.to_string(),
+            cost: self.config.cost(model, &usage),
+            usage,
+            latency_ms: latency.as_millis() as u64,
+            status: status(result)?,
+            batch: None,
+            id,
+        })
+    }
+
+    pub fn record_batch<T>(
+        &self,
+        model: &str,
+        name: &str,
+        result: &Result<T>,
+        usage: Usage,
+        latency: Duration,
+        id: Option<String>,
+    ) -> Result<()> {
+        self.append(Entry {
+            time: clock::timestamp(SystemTime::now()),
+            prompt: None,
+            model: model.to_string(),
+            cost: self
+                .config
+                .cost(model, &usage)
+                .map(|cost| cost * BATCH_DISCOUNT),
+            usage,
+            latency_ms: latency.as_millis() as u64,
+            status: status(result)?,
+            batch: Some(name.to_string()),
+            id,
+        })
+    }
+
+    pub fn record_batch_usage(&self, model: &str, name: &str, usage: Usage) -> Result<()> {
+        let _lock = self.lock()?;
+        let recorded = read(&self.path)?
+            .iter()
+            .any(|entry| entry.batch.as_deref() == Some(name) && entry.usage.total_tokens > 0);
+        if recorded {
+            return Ok(());
+        }
+        self.record_batch(model, name, &Ok(()), usage, Duration::ZERO, None)
+    }
+
+    fn append(&self, entry: Entry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.write_all(format!("{}\n", serde_json::to_string(&entry)?).as_bytes())?;
+        Ok(())
+    }
+}
+
+fn outstanding(entries: Vec<Entry>) -> Vec<Entry> {
+    let settled: HashSet<String> = entries
+        .iter()
+        .filter(|entry| entry.status != PENDING)
+        .filter_map(|entry| entry.id.clone())
+        .collect();
+    entries
+        .into_iter()
+        .filter(|entry| {
+            entry.status != PENDING || entry.id.as_ref().is_none_or(|id| !settled.contains(id))
+        })
+        .collect()
+}
+
+fn status<T>(result: &Result<T>) -> Result<String> {
+    Ok(match result {
+        Ok(_) => "ok".to_string(),
+        Err(e) => serde_json::to_value(error::kind(e))?
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+pub fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("paip").join("usage.jsonl"))
+}
+
+pub fn report(by: GroupBy, since: Option<&str>) -> Result<()> {
+    if let Some(since) = since {
+        anyhow::ensure!(
+            is_date(since),
+            "Invalid date '{since}', expected YYYY-MM-DD"
+        );
+    }
+    let path = path().ok_or_else(|| anyhow!("Could not find data directory"))?;
+    let entries: Vec<Entry> = read(&path)?
+        .into_iter()
+        .filter(|entry| entry.status != PENDING)
+        .filter(|entry| since.is_none_or(|since| entry.time.as_str() >= since))
+        .collect();
+
+    let rows = summarize(&entries, by);
+    let header = match by {
+        GroupBy::Day => "DAY",
+        GroupBy::Model => "MODEL",
+        GroupBy::Prompt => "PROMPT",
+    };
+    let width = rows
+        .keys()
+        .map(String::len)
+        .chain([header.len(), "TOTAL".len()])
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{header:<width$}  {:>6}  {:>6}  {:>12}  {:>10}",
+        "CALLS", "FAILED", "TOKENS", "COST"
+    );
+    let mut total = Row::default();
+    for (key, row) in &rows {
+        println!("{}", row.format(key, width));
+        total.add(row);
+    }
+    println!("{}", total.format("TOTAL", width));
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Row {
+    calls: u64,
+    failed: u64,
+    tokens: u64,
+    cost: f64,
+}
+
+impl Row {
+    fn add(&mut self, other: &Row) {
+        self.calls += other.calls;
+        self.failed += other.failed;
+        self.tokens += other.tokens;
+        self.cost += other.cost;
+    }
+
+    fn format(&self, key: &str, width: usize) -> String {
+        format!(
+            "{key:<width$}  {:>6}  {:>6}  {:>12}  {:>10}",
+            self.calls,
+            self.failed,
+            self.tokens,
+            format!("${:.4}", self.cost)
+        )
+    }
+}
+
+fn summarize(entries: &[Entry], by: GroupBy) -> BTreeMap<String, Row> {
+    let mut rows: BTreeMap<String, Row> = BTreeMap::new();
+    for entry in entries {
+        let key = match by {
+            GroupBy::Day => entry.time.get(..10).unwrap_or(&entry.time).to_string(),
+            GroupBy::Model => entry.model.clone(),
+            GroupBy::Prompt => entry.prompt.clone().unwrap_or_else(|| "-".to_string()),
+        };
+        rows.entry(key).or_default().add(&Row {
+            calls: 1,
+            failed: u64::from(entry.status != "ok"),
+            tokens: u64::from(entry.usage.total_tokens),
+            cost: entry.cost.unwrap_or_default(),
+        });
+    }
+    rows
+}
+
+fn totals(entries: &[Entry], prefix: &str) -> (u64, f64) {
+    entries
+        .iter()
+        .filter(|entry| entry.time.starts_with(prefix))
+        .fold((0, 0.0), |(tokens, cost), entry| {
+            (
+                tokens + u64::from(entry.usage.total_tokens),
+                cost + entry.cost.unwrap_or_default(),
+            )
+        })
+}
+
+fn read(path: &Path) -> Result<Vec<Entry>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", path.display(), e)),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+fn is_date(text: &str) -> bool {
+    text.len() == 10
+        && text.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(time: &str, model: &str, tokens: u32, cost: Option<f64>, status: &str) -> Entry {
+        Entry {
+            time: time.to_string(),
+            prompt: None,
+            model: model.to_string(),
+            usage: Usage {
+                total_tokens: tokens,
+                ..Default::default()
+            },
+            cost,
+            latency_ms: 100,
+            status: status.to_string(),
+            batch: None,
+            id: None,
+        }
+    }
+
+    fn write(path: &Path, entries: &[Entry]) -> Result<()> {
+        let lines: Vec<String> = entries
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<serde_json::Result<_>>()?;
+        fs::write(path, lines.join("\n") + "\n")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_cost_uses_longest_prefix_and_overrides() {
+        let usage = Usage {
+            prompt_tokens: 1_000_000,
+            output_tokens: 500_000,
+            thinking_tokens: 500_000,
+            total_tokens: 2_000_000,
+        };
+        let mut config = UsageConfig::default();
+        assert_eq!(config.cost("gemini-2.5-flash", &usage), Some(2.8));
+        assert_eq!(
+            config.cost("gemini-2.5-flash-lite-preview", &usage),
+            Some(0.5)
+        );
+        assert_eq!(config.cost("unknown-model", &usage), None);
+
+        config
+            .prices
+            .insert("unknown-model".to_string(), Price::new(1.0, 1.0));
+        assert_eq!(config.cost("unknown-model", &usage), Some(2.0));
+    }
+
+    #[test]
+    fn test_record_appends_entries() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("data").join("usage.jsonl");
+        let ledger = Ledger::new(path.clone(), UsageConfig::default());
+
+        let response = LlmResponse {
+            usage: Some(Usage {
+                prompt_tokens: 10,
+                output_tokens: 5,
+                thinking_tokens: 0,
+                total_tokens: 15,
+            }),
+            ..Default::default()
+        };
+        ledger.record(
+            "gemini-2.5-pro",
+            Some("review"),
+            &Ok(response),
+            Duration::from_millis(812),
+            None,
+        )?;
+        let failure = Err(error::classify(anyhow!("429"), ErrorKind::RateLimit));
+        ledger.record("gemini-2.5-pro", None, &failure, Duration::ZERO, None)?;
+
+        let entries = read(&path)?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].prompt.as_deref(), Some("review"));
+        assert_eq!(entries[0].usage.total_tokens, 15);
+        assert_eq!(entries[0].latency_ms, 812);
+        assert_eq!(entries[0].status, "ok");
+        assert_eq!(entries[1].status, "rate_limit");
+        assert_eq!(entries[1].usage, Usage::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserve_counts_until_recorded() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("usage.jsonl");
+        let budget = UsageConfig {
+            daily_tokens: Some(100),
+            ..Default::default()
+        };
+        let ledger = Ledger::new(path.clone(), budget);
+        let now = SystemTime::now();
+
+        let id = ledger.reserve("m", None, 400, now)?;
+        assert!(id.is_some());
+        let err = ledger.reserve("m", None, 4, now).unwrap_err();
+        assert_eq!(error::kind(&err), ErrorKind::Budget);
+
+        let response = LlmResponse {
+            usage: Some(Usage {
+                total_tokens: 40,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ledger.record("m", None, &Ok(response), Duration::ZERO, id)?;
+        assert!(ledger.reserve("m", None, 4, now)?.is_some());
+        assert_eq!(read(&path)?.len(), 3);
+        assert_eq!(outstanding(read(&path)?).len(), 2);
+
+        let ledger = Ledger::new(path, UsageConfig::default());
+        assert_eq!(ledger.reserve("m", None, 4, now)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_budget() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("usage.jsonl");
+        write(
+            &path,
+            &[
+                entry("2025-12-01T10:00:00.000Z", "m", 600, Some(1.5), "ok"),
+                entry("2025-12-17T10:00:00.000Z", "m", 300, Some(0.5), "ok"),
+                entry("2025-12-17T11:00:00.000Z", "m", 0, None, "network"),
+            ],
+        )?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_766_015_999);
+        let budget = |config: UsageConfig| Ledger::new(path.clone(), config).check_budget(now);
+
+        budget(UsageConfig {
+            daily_tokens: Some(301),
+            monthly_tokens: Some(901),
+            daily_cost: Some(0.6),
+            monthly_cost: Some(2.1),
+            ..Default::default()
+        })?;
+
+        let err = budget(UsageConfig {
+            daily_tokens: Some(300),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Daily token budget of 300 reached (300 tokens used today), refusing to send the request"
+        );
+        assert_eq!(error::kind(&err), ErrorKind::Budget);
+
+        let err = budget(UsageConfig {
+            monthly_cost: Some(2.0),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Monthly cost budget of $2.00 reached ($2.00 estimated this month)")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_summarize() {
+        let entries = [
+            entry("2025-12-16T10:00:00.000Z", "a", 10, Some(0.25), "ok"),
+            entry("2025-12-17T10:00:00.000Z", "b", 20, None, "auth"),
+            entry("2025-12-17T11:00:00.000Z", "a", 30, Some(0.5), "ok"),
+        ];
+        let rows = summarize(&entries, GroupBy::Day);
+        assert_eq!(
+            rows.keys().collect::<Vec<_>>(),
+            ["2025-12-16", "2025-12-17"]
+        );
+        assert_eq!(
+            rows["2025-12-17"],
+            Row {
+                calls: 2,
+                failed: 1,
+                tokens: 50,
+                cost: 0.5,
+            }
+        );
+
+        let rows = summarize(&entries, GroupBy::Model);
+        assert_eq!(rows["a"].tokens, 40);
+        assert_eq!(summarize(&entries, GroupBy::Prompt)["-"].calls, 3);
+    }
+
+    #[test]
+    fn test_is_date() {
+        assert!(is_date("2025-12-17"));
+        assert!(!is_date("2025-12"));
+        assert!(!is_date("17.12.2025"));
+    }
+}